@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::NaiveDate;
+
+use crate::errors::{OError, OResult};
+
+// Types of the optional schema in `o_config.toml`:
+//   [attributes]
+//   price = "number"
+//   bought = "date"
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+  String,
+  Number,
+  Date,
+  Bool,
+}
+
+// Dates are kept as `%Y-%m-%d` texts
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum AttributeValue {
+  String(String),
+  Number(f64),
+  Date(String),
+  Bool(bool),
+}
+
+impl AttributeType {
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "string" => Some(AttributeType::String),
+      "number" => Some(AttributeType::Number),
+      "date" => Some(AttributeType::Date),
+      "bool" => Some(AttributeType::Bool),
+      _ => None,
+    }
+  }
+
+  pub fn get_name(self) -> &'static str {
+    match self {
+      AttributeType::String => "string",
+      AttributeType::Number => "number",
+      AttributeType::Date => "date",
+      AttributeType::Bool => "bool",
+    }
+  }
+}
+
+impl AttributeValue {
+  pub fn get_type(&self) -> AttributeType {
+    match self {
+      AttributeValue::String(_) => AttributeType::String,
+      AttributeValue::Number(_) => AttributeType::Number,
+      AttributeValue::Date(_) => AttributeType::Date,
+      AttributeValue::Bool(_) => AttributeType::Bool,
+    }
+  }
+}
+
+pub type Attributes = BTreeMap<String, AttributeValue>;
+
+pub type AttributesSchema = BTreeMap<String, AttributeType>;
+
+// Names of the record fields and of the output columns
+const RESERVED_KEYS: &[&str] = &[
+  "what",
+  "what_id",
+  "id",
+  "location",
+  "location_id",
+  "notes",
+  "created",
+  "updated",
+  "tags",
+  "tag",
+  "quantity",
+  "min_quantity",
+  "path",
+  "change",
+];
+
+impl fmt::Display for AttributeValue {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AttributeValue::String(value) | AttributeValue::Date(value) => write!(f, "{}", value),
+      AttributeValue::Number(value) => write!(f, "{}", value),
+      AttributeValue::Bool(value) => write!(f, "{}", value),
+    }
+  }
+}
+
+pub fn parse_date(value: &str) -> Option<NaiveDate> {
+  NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+// Identifiers like 00123 or 1e3 are kept as strings, they would lose their
+// text as numbers
+fn get_is_inferred_number(value: &str) -> bool {
+  let digits = value.trim_start_matches(['+', '-']);
+  let has_leading_zero = digits.starts_with('0')
+    && digits[1..].starts_with(|character: char| character.is_ascii_digit());
+
+  !has_leading_zero
+    && value
+      .chars()
+      .all(|character| character.is_ascii_digit() || "+-.".contains(character))
+}
+
+// Without a type in the schema it is inferred from the value
+pub fn parse_attribute_value(
+  key: &str,
+  value: &str,
+  attribute_type: Option<AttributeType>,
+) -> OResult<AttributeValue> {
+  let get_error = |type_name: &str| {
+    OError::InvalidArgument(format!(
+      "The attribute {} must be a {}, found: {}",
+      key, type_name, value
+    ))
+  };
+
+  match attribute_type {
+    Some(AttributeType::String) => Ok(AttributeValue::String(value.to_string())),
+    // the data file can't store NaN or infinity
+    Some(AttributeType::Number) => value
+      .parse::<f64>()
+      .ok()
+      .filter(|number| number.is_finite())
+      .map(AttributeValue::Number)
+      .ok_or_else(|| get_error("number")),
+    Some(AttributeType::Date) => parse_date(value)
+      .map(|date| AttributeValue::Date(date.format("%Y-%m-%d").to_string()))
+      .ok_or_else(|| get_error("date like 2024-01-31")),
+    Some(AttributeType::Bool) => value
+      .parse::<bool>()
+      .map(AttributeValue::Bool)
+      .map_err(|_| get_error("bool (true or false)")),
+    None => {
+      if get_is_inferred_number(value) {
+        if let Ok(parsed) = parse_attribute_value(key, value, Some(AttributeType::Number)) {
+          return Ok(parsed);
+        }
+      }
+
+      // only when the value is written the same way, `True` stays a string
+      for attribute_type in &[AttributeType::Bool, AttributeType::Date] {
+        match parse_attribute_value(key, value, Some(*attribute_type)) {
+          Ok(parsed) if parsed.to_string() == value => return Ok(parsed),
+          _ => {}
+        }
+      }
+
+      Ok(AttributeValue::String(value.to_string()))
+    }
+  }
+}
+
+pub fn parse_attribute_key(key: &str) -> OResult<String> {
+  let key = key.trim().to_lowercase();
+  let is_valid = !key.is_empty()
+    && key
+      .chars()
+      .all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+
+  if !is_valid || RESERVED_KEYS.contains(&key.as_str()) {
+    return Err(OError::InvalidArgument(format!(
+      "Invalid attribute name {}: it can only have letters, numbers, _ and -, and not be a field name",
+      key
+    )));
+  }
+
+  Ok(key)
+}
+
+// Parses `key=value` assignments, an empty value removes the attribute
+pub fn parse_assignments(
+  assignments: &[&str],
+  schema: &AttributesSchema,
+) -> OResult<Vec<(String, Option<AttributeValue>)>> {
+  assignments
+    .iter()
+    .map(|assignment| {
+      let (key, value) = assignment.split_once('=').ok_or_else(|| {
+        OError::InvalidArgument(format!(
+          "Expected an attribute like key=value, found: {}",
+          assignment
+        ))
+      })?;
+      let key = parse_attribute_key(key)?;
+      let value = value.trim();
+
+      if value.is_empty() {
+        return Ok((key, None));
+      }
+
+      let value = parse_attribute_value(&key, value, schema.get(&key).cloned())?;
+
+      Ok((key, Some(value)))
+    })
+    .collect()
+}
+
+pub fn format_attributes(attributes: &Attributes) -> String {
+  attributes
+    .iter()
+    .map(|(key, value)| format!("{}={}", key, value))
+    .collect::<Vec<String>>()
+    .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn infer(value: &str) -> AttributeValue {
+    parse_attribute_value("key", value, None).unwrap()
+  }
+
+  #[test]
+  fn infers_decimals_as_numbers() {
+    assert_eq!(infer("19.50"), AttributeValue::Number(19.5));
+    assert_eq!(infer("20.0"), AttributeValue::Number(20.0));
+    assert_eq!(infer("+5"), AttributeValue::Number(5.0));
+    assert_eq!(infer("-0.5"), AttributeValue::Number(-0.5));
+    assert_eq!(infer("0"), AttributeValue::Number(0.0));
+  }
+
+  #[test]
+  fn keeps_identifiers_as_strings() {
+    for value in &[
+      "00123", "0123", "-007", "1e3", "1E3", "inf", "NaN", "1.2.3", "+",
+    ] {
+      assert_eq!(infer(value), AttributeValue::String(value.to_string()));
+    }
+  }
+
+  #[test]
+  fn infers_only_canonical_bools_and_dates() {
+    assert_eq!(infer("true"), AttributeValue::Bool(true));
+    assert_eq!(infer("True"), AttributeValue::String("True".to_string()));
+    assert_eq!(
+      infer("2024-01-31"),
+      AttributeValue::Date("2024-01-31".to_string())
+    );
+    assert_eq!(
+      infer("2024-1-31"),
+      AttributeValue::String("2024-1-31".to_string())
+    );
+  }
+
+  #[test]
+  fn rejects_non_finite_numbers() {
+    assert!(parse_attribute_value("key", "NaN", Some(AttributeType::Number)).is_err());
+    assert!(parse_attribute_value("key", "inf", Some(AttributeType::Number)).is_err());
+  }
+}
@@ -0,0 +1,360 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::data::{get_changed_fields, get_context, Context, Record};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum IssueKind {
+  RepeatedId,
+  NameMismatch,
+  LocationNameDrift,
+  SharedName,
+  Cycle,
+}
+
+impl IssueKind {
+  pub fn get_label(self) -> &'static str {
+    match self {
+      IssueKind::RepeatedId => "Repeated id",
+      IssueKind::NameMismatch => "Name mismatch",
+      IssueKind::LocationNameDrift => "Location name drift",
+      IssueKind::SharedName => "Shared name",
+      IssueKind::Cycle => "Cycle",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Issue {
+  pub kind: IssueKind,
+  pub description: String,
+  pub records: Vec<Record>,
+  pub fixable: bool,
+}
+
+fn get_id_groups(records: &[Record]) -> Vec<(usize, Vec<&Record>)> {
+  let mut groups: Vec<(usize, Vec<&Record>)> = vec![];
+
+  for record in records {
+    match groups.iter_mut().find(|(id, _)| *id == record.what_id) {
+      Some((_, group)) => group.push(record),
+      None => groups.push((record.what_id, vec![record])),
+    }
+  }
+
+  groups
+}
+
+// Exact copies can be dropped and the rest renumbered, as long as the
+// remaining records of the group have different names
+fn get_is_id_group_fixable(group: &[&Record]) -> bool {
+  let mut kept: Vec<&Record> = vec![];
+
+  for record in group {
+    if kept
+      .iter()
+      .any(|kept_record| get_changed_fields(kept_record, record).is_empty())
+    {
+      continue;
+    }
+
+    if kept
+      .iter()
+      .any(|kept_record| kept_record.what == record.what)
+    {
+      return false;
+    }
+
+    kept.push(record);
+  }
+
+  true
+}
+
+// Location names used by the records inside `id`, in order of appearance
+fn get_location_names(records: &[Record], id: usize) -> Vec<(String, usize)> {
+  let mut names: Vec<(String, usize)> = vec![];
+
+  for record in records.iter().filter(|record| record.location_id == id) {
+    match names.iter_mut().find(|(name, _)| *name == record.location) {
+      Some((_, count)) => *count += 1,
+      None => names.push((record.location.clone(), 1)),
+    }
+  }
+
+  names
+}
+
+// The most used name, the first one on ties
+fn get_main_location_name(names: &[(String, usize)]) -> String {
+  let mut main_name = &names[0];
+
+  for name in names {
+    if name.1 > main_name.1 {
+      main_name = name;
+    }
+  }
+
+  main_name.0.clone()
+}
+
+// Each cycle is returned once, starting from its lowest id
+fn get_cycles(context: &Context) -> Vec<Vec<usize>> {
+  let mut cycles: Vec<Vec<usize>> = vec![];
+  let mut ids: Vec<&usize> = context.hierarchy.keys().collect();
+
+  ids.sort();
+
+  for id in ids {
+    let mut chain: Vec<usize> = vec![*id];
+    let mut current_id = *id;
+
+    while let Some(parent_id) = context
+      .hierarchy
+      .get(&current_id)
+      .and_then(|node| node.parent)
+    {
+      if let Some(position) = chain.iter().position(|chain_id| *chain_id == parent_id) {
+        let mut cycle = chain[position..].to_vec();
+        let min_position = (0..cycle.len()).min_by_key(|idx| cycle[*idx]).unwrap();
+
+        cycle.rotate_left(min_position);
+
+        if !cycles.contains(&cycle) {
+          cycles.push(cycle);
+        }
+
+        break;
+      }
+
+      chain.push(parent_id);
+      current_id = parent_id;
+    }
+  }
+
+  cycles
+}
+
+fn get_id_text(context: &Context, id: usize) -> String {
+  match context.id_to_str_map.get(&id) {
+    Some(name) => format!("{} [{}]", name, id),
+    None => format!("[{}]", id),
+  }
+}
+
+pub fn check_records(records: &[Record]) -> Vec<Issue> {
+  let context = get_context(records);
+  let mut issues: Vec<Issue> = vec![];
+  let id_groups = get_id_groups(records);
+
+  for (id, group) in id_groups.iter().filter(|(_, group)| group.len() > 1) {
+    issues.push(Issue {
+      kind: IssueKind::RepeatedId,
+      description: format!("{} records use the id [{}]", group.len(), id),
+      records: group.iter().map(|record| (*record).clone()).collect(),
+      fixable: get_is_id_group_fixable(group),
+    });
+  }
+
+  for (id, group) in id_groups.iter().filter(|(_, group)| group.len() == 1) {
+    let item = group[0];
+    let mismatched: Vec<Record> = records
+      .iter()
+      .filter(|record| record.location_id == *id && record.location != item.what)
+      .cloned()
+      .collect();
+
+    if !mismatched.is_empty() {
+      issues.push(Issue {
+        kind: IssueKind::NameMismatch,
+        description: format!(
+          "{} [{}] is named differently by {} record(s) inside it",
+          item.what,
+          id,
+          mismatched.len()
+        ),
+        records: [vec![item.clone()], mismatched].concat(),
+        fixable: true,
+      });
+    }
+  }
+
+  let mut location_ids: Vec<usize> = records
+    .iter()
+    .map(|record| record.location_id)
+    .filter(|id| !context.id_to_record_idx_map.contains_key(id))
+    .collect::<HashSet<usize>>()
+    .into_iter()
+    .collect();
+
+  location_ids.sort();
+
+  for id in location_ids {
+    let names = get_location_names(records, id);
+
+    if names.len() > 1 {
+      issues.push(Issue {
+        kind: IssueKind::LocationNameDrift,
+        description: format!(
+          "The location [{}] is named {}",
+          id,
+          names
+            .iter()
+            .map(|(name, _)| format!("\"{}\"", name))
+            .collect::<Vec<String>>()
+            .join(", ")
+        ),
+        records: records
+          .iter()
+          .filter(|record| record.location_id == id)
+          .cloned()
+          .collect(),
+        fixable: true,
+      });
+    }
+  }
+
+  let mut name_ids: Vec<(String, Vec<usize>)> = vec![];
+
+  for record in records {
+    for (name, id) in &[
+      (&record.what, record.what_id),
+      (&record.location, record.location_id),
+    ] {
+      match name_ids.iter_mut().find(|(existing, _)| existing == *name) {
+        Some((_, ids)) if !ids.contains(id) => ids.push(*id),
+        Some(_) => {}
+        None => name_ids.push(((*name).clone(), vec![*id])),
+      }
+    }
+  }
+
+  for (name, ids) in name_ids.iter().filter(|(_, ids)| ids.len() > 1) {
+    issues.push(Issue {
+      kind: IssueKind::SharedName,
+      description: format!(
+        "\"{}\" is used by the ids {}, rename one of them with `o ed`",
+        name,
+        ids
+          .iter()
+          .map(|id| format!("[{}]", id))
+          .collect::<Vec<String>>()
+          .join(", ")
+      ),
+      records: records
+        .iter()
+        .filter(|record| record.what == *name || record.location == *name)
+        .cloned()
+        .collect(),
+      fixable: false,
+    });
+  }
+
+  for cycle in get_cycles(&context) {
+    // the cycle goes from children to parents, the path is displayed the other way
+    let path: Vec<String> = cycle
+      .iter()
+      .chain(cycle.first())
+      .rev()
+      .map(|id| get_id_text(&context, *id))
+      .collect();
+
+    issues.push(Issue {
+      kind: IssueKind::Cycle,
+      description: format!(
+        "{} loops back to itself, break it with `o mv`",
+        path.join(" > ")
+      ),
+      records: records
+        .iter()
+        .filter(|record| cycle.contains(&record.what_id))
+        .cloned()
+        .collect(),
+      fixable: false,
+    });
+  }
+
+  issues
+}
+
+// Applies the repairs of the fixable issues: exact copies are dropped,
+// repeated ids are renumbered (with the records inside them that use their
+// name) and the location names are aligned with the item names, or with the
+// most used name for locations without a record
+pub fn fix_records(records: &[Record]) -> Vec<Record> {
+  let mut next_id = get_context(records).max_id + 1;
+  let fixable_ids: Vec<usize> = get_id_groups(records)
+    .iter()
+    .filter(|(_, group)| group.len() > 1 && get_is_id_group_fixable(group))
+    .map(|(id, _)| *id)
+    .collect();
+  let mut kept: Vec<&Record> = vec![];
+  let mut renumbered: Vec<(usize, usize, String)> = vec![];
+  let mut new_records: Vec<Record> = vec![];
+
+  for record in records {
+    if !fixable_ids.contains(&record.what_id) {
+      new_records.push(record.clone());
+      continue;
+    }
+
+    let group_kept: Vec<&&Record> = kept
+      .iter()
+      .filter(|kept_record| kept_record.what_id == record.what_id)
+      .collect();
+
+    if group_kept
+      .iter()
+      .any(|kept_record| get_changed_fields(kept_record, record).is_empty())
+    {
+      continue;
+    }
+
+    if group_kept.is_empty() {
+      new_records.push(record.clone());
+    } else {
+      renumbered.push((record.what_id, next_id, record.what.clone()));
+      new_records.push(Record {
+        what_id: next_id,
+        ..record.clone()
+      });
+      next_id += 1;
+    }
+
+    kept.push(record);
+  }
+
+  for record in new_records.iter_mut() {
+    for (old_id, new_id, name) in &renumbered {
+      if record.location_id == *old_id && record.location == *name {
+        record.location_id = *new_id;
+      }
+    }
+  }
+
+  let context = get_context(&new_records);
+  let id_groups = get_id_groups(&new_records);
+  let mut names: HashMap<usize, String> = HashMap::new();
+
+  for (id, group) in id_groups.iter().filter(|(_, group)| group.len() == 1) {
+    names.insert(*id, group[0].what.clone());
+  }
+
+  for record in &new_records {
+    let id = record.location_id;
+
+    if !names.contains_key(&id) && !context.id_to_record_idx_map.contains_key(&id) {
+      names.insert(
+        id,
+        get_main_location_name(&get_location_names(&new_records, id)),
+      );
+    }
+  }
+
+  for record in new_records.iter_mut() {
+    if let Some(name) = names.get(&record.location_id) {
+      record.location = name.clone();
+    }
+  }
+
+  new_records
+}
@@ -1,10 +1,24 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-#[derive(Debug, Deserialize)]
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, TimeZone};
+
+use crate::attributes::{format_attributes, Attributes, AttributesSchema};
+use crate::errors::{OError, OResult};
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
   pub encryption_key: String,
   pub saved_actions: usize,
+  // strftime format used to display the dates
+  #[serde(default = "get_default_date_format")]
+  pub date_format: String,
+  // Set by `rekey` while the files are rewritten, the files it didn't reach yet
+  // are still read with this key
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub previous_encryption_key: Option<String>,
+  #[serde(default)]
+  pub attributes: AttributesSchema,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -16,32 +30,164 @@ pub struct Record {
   pub updated: String,
   pub what: String,
   pub what_id: usize,
+  #[serde(default)]
+  pub tags: Vec<String>,
+  #[serde(default)]
+  pub attributes: Attributes,
+  #[serde(default)]
+  pub quantity: Option<usize>,
+  // the item is low on stock when the quantity reaches it
+  #[serde(default)]
+  pub min_quantity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldChange {
+  pub field: String,
+  pub before: String,
+  pub after: String,
 }
 
-impl Record {
-  pub fn print_line(&self) {
-    print!("- {}", &self.what);
-    print!(" [{}]", &self.what_id);
-    print!(" | ");
-    print!("{}", &self.location);
-    print!(" [{}]", &self.location_id);
-    print!(" | ");
-    print!("{}", &self.updated);
-    print!(" | ");
-    print!("{}", &self.notes);
-    println!();
+fn format_quantity(quantity: Option<usize>) -> String {
+  quantity.map_or_else(String::new, |quantity| quantity.to_string())
+}
+
+pub fn get_changed_fields(before: &Record, after: &Record) -> Vec<FieldChange> {
+  let fields = [
+    ("what", before.what.clone(), after.what.clone()),
+    (
+      "what_id",
+      before.what_id.to_string(),
+      after.what_id.to_string(),
+    ),
+    ("location", before.location.clone(), after.location.clone()),
+    (
+      "location_id",
+      before.location_id.to_string(),
+      after.location_id.to_string(),
+    ),
+    ("notes", before.notes.clone(), after.notes.clone()),
+    ("created", before.created.clone(), after.created.clone()),
+    ("updated", before.updated.clone(), after.updated.clone()),
+    ("tags", before.tags.join(", "), after.tags.join(", ")),
+    (
+      "attributes",
+      format_attributes(&before.attributes),
+      format_attributes(&after.attributes),
+    ),
+    (
+      "quantity",
+      format_quantity(before.quantity),
+      format_quantity(after.quantity),
+    ),
+    (
+      "min_quantity",
+      format_quantity(before.min_quantity),
+      format_quantity(after.min_quantity),
+    ),
+  ];
+
+  fields
+    .iter()
+    .filter(|(_, before, after)| before != after)
+    .map(|(field, before, after)| FieldChange {
+      field: field.to_string(),
+      before: before.clone(),
+      after: after.clone(),
+    })
+    .collect()
+}
+
+pub fn get_is_low_stock(record: &Record) -> bool {
+  match (record.quantity, record.min_quantity) {
+    (Some(quantity), Some(min_quantity)) => quantity <= min_quantity,
+    _ => false,
   }
+}
 
-  pub fn print_location_with_count(&self, context: &Context) {
-    let count = context.hierarchy[&self.location_id].children.len();
+// Display format of the dates when `date_format` is not in the config
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
 
-    print!("- {}", &self.location);
-    print!(" [{}]", &self.location_id);
-    print!(" <{} items>", count);
-    println!();
+fn get_default_date_format() -> String {
+  DEFAULT_DATE_FORMAT.to_string()
+}
+
+// The records store RFC3339 timestamps
+pub fn get_now_date() -> String {
+  Local::now().to_rfc3339_opts(SecondsFormat::Secs, false)
+}
+
+// Accepts RFC3339 timestamps as well as the `%d/%m/%y` dates of the old
+// records and `%Y-%m-%d` dates, which are taken as the local midnight
+pub fn parse_record_timestamp(date: &str) -> Option<DateTime<Local>> {
+  DateTime::parse_from_rfc3339(date)
+    .ok()
+    .map(|date| date.with_timezone(&Local))
+    .or_else(|| {
+      let date = NaiveDate::parse_from_str(date, "%d/%m/%y")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%Y-%m-%d"))
+        .ok()?;
+
+      Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+    })
+}
+
+// The RFC3339 timestamps are kept as they are, the other dates are converted
+fn normalize_record_date(date: &str) -> Option<String> {
+  if DateTime::parse_from_rfc3339(date).is_ok() {
+    return Some(date.to_string());
+  }
+
+  parse_record_timestamp(date)
+    .map(|timestamp| timestamp.to_rfc3339_opts(SecondsFormat::Secs, false))
+}
+
+// The `created` and `updated` of the imported items, None when they are empty
+pub fn parse_import_date(what: &str, field: &str, date: Option<&str>) -> OResult<Option<String>> {
+  match date.map(str::trim).filter(|date| !date.is_empty()) {
+    None => Ok(None),
+    Some(date) => normalize_record_date(date).map(Some).ok_or_else(|| {
+      OError::InvalidArgument(format!(
+        "The {} date of {} must be a timestamp like 2024-01-31T10:00:00+01:00 or a date like \
+         2024-01-31, found: {}",
+        field, what, date
+      ))
+    }),
   }
 }
 
+pub fn parse_record_date(date: &str) -> Option<NaiveDate> {
+  parse_record_timestamp(date).map(|date| date.naive_local().date())
+}
+
+fn migrate_date(date: &mut String) {
+  if let Some(normalized) = normalize_record_date(date) {
+    *date = normalized;
+  }
+}
+
+// Converts the dates of the old records that aren't RFC3339 timestamps, the
+// values that can't be parsed are kept as they are
+pub fn migrate_record_dates(records: &mut [Record]) {
+  for record in records.iter_mut() {
+    migrate_date(&mut record.created);
+    migrate_date(&mut record.updated);
+  }
+}
+
+pub fn format_record_date(date: &str, date_format: &str) -> String {
+  match parse_record_timestamp(date) {
+    Some(timestamp) => timestamp.format(date_format).to_string(),
+    None => date.to_string(),
+  }
+}
+
+pub fn get_empty_notes_text() -> String {
+  "N/A".to_string()
+}
+
 #[derive(Debug, Clone)]
 pub struct TreeNode {
   pub children: HashSet<usize>,
@@ -57,6 +203,63 @@ pub struct Context {
   pub max_id: usize,
 }
 
+impl Context {
+  // Ids from the root down to `id`, stops at cycles
+  pub fn get_path_ids(&self, id: usize) -> Vec<usize> {
+    let mut path = vec![id];
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut current_id = id;
+
+    visited.insert(id);
+
+    while let Some(parent_id) = self.hierarchy.get(&current_id).and_then(|node| node.parent) {
+      if !visited.insert(parent_id) {
+        break;
+      }
+
+      path.push(parent_id);
+      current_id = parent_id;
+    }
+
+    path.reverse();
+
+    path
+  }
+
+  // All the ids under `id` in the hierarchy, stops at cycles
+  pub fn get_descendant_ids(&self, id: usize) -> Vec<usize> {
+    let mut descendant_ids: Vec<usize> = vec![];
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut pending_ids: Vec<usize> = vec![id];
+
+    visited.insert(id);
+
+    while let Some(current_id) = pending_ids.pop() {
+      if let Some(node) = self.hierarchy.get(&current_id) {
+        for child_id in &node.children {
+          if visited.insert(*child_id) {
+            descendant_ids.push(*child_id);
+            pending_ids.push(*child_id);
+          }
+        }
+      }
+    }
+
+    descendant_ids
+  }
+
+  pub fn get_path_names(&self, id: usize) -> Vec<String> {
+    self
+      .get_path_ids(id)
+      .iter()
+      .map(|path_id| match self.id_to_str_map.get(path_id) {
+        Some(name) => name.clone(),
+        None => path_id.to_string(),
+      })
+      .collect()
+  }
+}
+
 pub fn get_context(records: &[Record]) -> Context {
   let mut str_to_id_map: HashMap<String, usize> = HashMap::new();
   let mut id_to_str_map: HashMap<usize, String> = HashMap::new();
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::data::{get_changed_fields, get_context, FieldChange, Record};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocationMove {
+  pub before: Vec<String>,
+  pub after: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedRecord {
+  pub before: Record,
+  pub after: Record,
+  pub fields: Vec<FieldChange>,
+  pub location_move: Option<LocationMove>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DataDiff {
+  pub added: Vec<Record>,
+  pub removed: Vec<Record>,
+  pub changed: Vec<ChangedRecord>,
+}
+
+impl DataDiff {
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+  }
+}
+
+// Matches the records by `what_id`, the location paths are resolved with the
+// hierarchy of each side
+pub fn diff_data(old_records: &[Record], new_records: &[Record]) -> DataDiff {
+  let old_context = get_context(old_records);
+  let new_context = get_context(new_records);
+  let new_by_id: HashMap<usize, &Record> = new_records
+    .iter()
+    .map(|record| (record.what_id, record))
+    .collect();
+  let mut diff = DataDiff::default();
+
+  for old_record in old_records {
+    let new_record = match new_by_id.get(&old_record.what_id) {
+      Some(new_record) => *new_record,
+      None => {
+        diff.removed.push(old_record.clone());
+        continue;
+      }
+    };
+    let fields = get_changed_fields(old_record, new_record);
+
+    if fields.is_empty() {
+      continue;
+    }
+
+    let before_path = old_context.get_path_names(old_record.location_id);
+    let after_path = new_context.get_path_names(new_record.location_id);
+
+    diff.changed.push(ChangedRecord {
+      before: old_record.clone(),
+      after: new_record.clone(),
+      fields,
+      location_move: if before_path != after_path {
+        Some(LocationMove {
+          before: before_path,
+          after: after_path,
+        })
+      } else {
+        None
+      },
+    });
+  }
+
+  let old_ids: HashSet<usize> = old_records.iter().map(|record| record.what_id).collect();
+
+  for new_record in new_records {
+    if !old_ids.contains(&new_record.what_id) {
+      diff.added.push(new_record.clone());
+    }
+  }
+
+  diff
+}
@@ -0,0 +1,64 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+// Layout of an encrypted file: MAGIC | salt | nonce | ciphertext (with the auth tag)
+const MAGIC: &[u8] = b"o-encrypted-v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+pub fn get_is_encrypted(contents: &[u8]) -> bool {
+  contents.starts_with(MAGIC)
+}
+
+fn derive_key(encryption_key: &str, salt: &[u8]) -> Key {
+  let mut key = Key::default();
+
+  Argon2::default()
+    .hash_password_into(encryption_key.as_bytes(), salt, key.as_mut_slice())
+    .expect("Unable to derive the encryption key");
+
+  key
+}
+
+pub fn encrypt(plain: &[u8], encryption_key: &str) -> Vec<u8> {
+  let mut salt = [0u8; SALT_LEN];
+  let mut nonce = [0u8; NONCE_LEN];
+
+  OsRng.fill_bytes(&mut salt);
+  OsRng.fill_bytes(&mut nonce);
+
+  let cipher = ChaCha20Poly1305::new(&derive_key(encryption_key, &salt));
+  let ciphertext = cipher
+    .encrypt(Nonce::from_slice(&nonce), plain)
+    .expect("Unable to encrypt the data");
+
+  [MAGIC, &salt, &nonce, &ciphertext].concat()
+}
+
+// Returns None when the key is wrong or the contents were tampered with
+pub fn decrypt(contents: &[u8], encryption_key: &str) -> Option<Vec<u8>> {
+  let header_len = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+  if !get_is_encrypted(contents) || contents.len() < header_len {
+    return None;
+  }
+
+  let salt = &contents[MAGIC.len()..MAGIC.len() + SALT_LEN];
+  let nonce = &contents[MAGIC.len() + SALT_LEN..header_len];
+  let cipher = ChaCha20Poly1305::new(&derive_key(encryption_key, salt));
+
+  cipher
+    .decrypt(Nonce::from_slice(nonce), &contents[header_len..])
+    .ok()
+}
+
+// Key written by `init`, 32 random bytes as hex so it fits in o_config.toml
+pub fn generate_key() -> String {
+  let mut key = [0u8; 32];
+
+  OsRng.fill_bytes(&mut key);
+
+  key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
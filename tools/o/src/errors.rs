@@ -0,0 +1,71 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum OError {
+  MissingProject,
+  UnknownId(usize),
+  DuplicateWhat(String),
+  CorruptData(String),
+  BadConfig(String),
+  Io(std::io::Error),
+  WrongKey(String),
+  InvalidArgument(String),
+  EmptyJournal(String),
+  Locked(String),
+}
+
+pub type OResult<T> = Result<T, OError>;
+
+impl OError {
+  // Exit codes are part of the CLI contract, so scripts can branch on them
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      OError::MissingProject => 2,
+      OError::UnknownId(_) => 3,
+      OError::DuplicateWhat(_) => 4,
+      OError::CorruptData(_) => 5,
+      OError::BadConfig(_) => 6,
+      OError::Io(_) => 7,
+      OError::WrongKey(_) => 8,
+      OError::InvalidArgument(_) => 9,
+      OError::EmptyJournal(_) => 10,
+      OError::Locked(_) => 11,
+    }
+  }
+}
+
+impl fmt::Display for OError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      OError::MissingProject => write!(
+        f,
+        "There is no .o directory present locally or in the home directory"
+      ),
+      OError::UnknownId(id) => write!(f, "Unexisting id {}", id),
+      OError::DuplicateWhat(what) => write!(f, "Duplicated what: {}", what),
+      OError::CorruptData(details) => write!(f, "Corrupt data: {}", details),
+      OError::BadConfig(details) => write!(f, "Bad config: {}", details),
+      OError::Io(err) => write!(f, "IO failure: {}", err),
+      OError::WrongKey(file_path) => write!(
+        f,
+        "Unable to decrypt {}: `encryption_key` in o_config.toml is wrong or the file is corrupted",
+        file_path
+      ),
+      OError::InvalidArgument(details) => write!(f, "{}", details),
+      OError::EmptyJournal(details) => write!(f, "{}", details),
+      OError::Locked(holder) => write!(
+        f,
+        "The .o directory is locked by {}, retry later or drop --no-wait",
+        holder
+      ),
+    }
+  }
+}
+
+impl std::error::Error for OError {}
+
+impl From<std::io::Error> for OError {
+  fn from(err: std::io::Error) -> Self {
+    OError::Io(err)
+  }
+}
@@ -0,0 +1,1196 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs::read_to_string;
+
+use serde_yaml::value::Tag;
+use serde_yaml::{Mapping, Value};
+
+use crate::attributes::{
+  parse_attribute_key, parse_attribute_value, AttributeType, AttributeValue, Attributes,
+  AttributesSchema,
+};
+use crate::data::{get_empty_notes_text, get_now_date, parse_import_date, Context, Record};
+use crate::errors::{OError, OResult};
+use crate::store::{Store, TreeItem, TreeOptions};
+
+// Formats of `o export` and `o import`, the graphs can only be exported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExchangeFormat {
+  Jsonl,
+  Yaml,
+  Markdown,
+  Dot,
+  Mermaid,
+}
+
+// Extra lines of the nodes of the graphs, below the names
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphLabel {
+  Notes,
+  Count,
+}
+
+// `root` and `labels` are only used by the graphs
+#[derive(Debug, Clone, Default)]
+pub struct ExportOptions {
+  pub root: Option<usize>,
+  pub labels: Vec<GraphLabel>,
+}
+
+impl ExchangeFormat {
+  pub fn parse(format: &str) -> OResult<Self> {
+    match format {
+      "jsonl" => Ok(ExchangeFormat::Jsonl),
+      "yaml" | "yml" => Ok(ExchangeFormat::Yaml),
+      "markdown" | "md" => Ok(ExchangeFormat::Markdown),
+      "dot" => Ok(ExchangeFormat::Dot),
+      "mermaid" => Ok(ExchangeFormat::Mermaid),
+      _ => Err(OError::InvalidArgument(format!(
+        "Unknown format {}, expected one of: jsonl, yaml, markdown, dot, mermaid",
+        format
+      ))),
+    }
+  }
+
+  fn get_is_graph(self) -> bool {
+    self == ExchangeFormat::Dot || self == ExchangeFormat::Mermaid
+  }
+}
+
+impl GraphLabel {
+  pub fn parse(label: &str) -> OResult<Self> {
+    match label {
+      "notes" => Ok(GraphLabel::Notes),
+      "count" => Ok(GraphLabel::Count),
+      _ => Err(OError::InvalidArgument(format!(
+        "Unknown label {}, expected one of: notes, count",
+        label
+      ))),
+    }
+  }
+}
+
+// A record read from a file, the missing ids are given by the names in
+// `get_drafts_records`
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RecordDraft {
+  what_id: Option<usize>,
+  what: String,
+  location_id: Option<usize>,
+  location: String,
+  created: Option<String>,
+  updated: Option<String>,
+  notes: Option<String>,
+  #[serde(default)]
+  tags: Vec<String>,
+  #[serde(default)]
+  attributes: Attributes,
+  quantity: Option<usize>,
+  min_quantity: Option<usize>,
+}
+
+// Text of a file, the quoted values are always strings
+#[derive(Debug, Clone, PartialEq)]
+struct TextValue {
+  text: String,
+  is_quoted: bool,
+}
+
+// An item of the Markdown outline, the top-level ones are the locations
+struct OutlineItem {
+  name: String,
+  id: Option<usize>,
+  indent: usize,
+  parent: Option<usize>,
+  details: Vec<(String, TextValue, usize)>,
+}
+
+const YAML_KEYWORDS: &[&str] = &["true", "false", "yes", "no", "on", "off", "null", "~"];
+
+fn get_file_error<E: Display>(file_path: &str, err: E) -> OError {
+  OError::CorruptData(format!("{}: {}", file_path, err))
+}
+
+fn get_line_error<E: Display>(line: usize, err: E) -> String {
+  format!("line {}: {}", line, err)
+}
+
+fn quote_text(text: &str) -> String {
+  serde_json::to_string(text).unwrap_or_default()
+}
+
+// The YAML strings that can be written without quotes and read back the same
+fn get_is_plain_yaml(text: &str) -> bool {
+  !text.is_empty()
+    && !YAML_KEYWORDS.contains(&text.to_lowercase().as_str())
+    && text.parse::<f64>().is_err()
+    && text.starts_with(|c: char| c.is_alphanumeric() || "_./(".contains(c))
+    && !text.ends_with([' ', ':'])
+    && !text.contains(": ")
+    && text
+      .chars()
+      .all(|c| c.is_alphanumeric() || " _-./()+:@&".contains(c))
+}
+
+fn get_is_plain_markdown(text: &str) -> bool {
+  !text.is_empty() && text.trim() == text && !text.starts_with(['"', '\'']) && !text.contains('\n')
+}
+
+fn format_yaml_text(text: &str) -> String {
+  if get_is_plain_yaml(text) {
+    text.to_string()
+  } else {
+    quote_text(text)
+  }
+}
+
+// The strings that would be read as another type are quoted
+fn format_attribute_value(
+  key: &str,
+  value: &AttributeValue,
+  get_is_plain: fn(&str) -> bool,
+) -> String {
+  match value {
+    AttributeValue::String(text) => {
+      let is_inferred = parse_attribute_value(key, text, None).ok().as_ref() == Some(value);
+
+      if is_inferred && get_is_plain(text) {
+        text.clone()
+      } else {
+        quote_text(text)
+      }
+    }
+    value => value.to_string(),
+  }
+}
+
+// The strings are always quoted, YAML reads the plain ones as numbers or bools
+fn format_yaml_attribute(key: &str, value: &AttributeValue) -> String {
+  match value {
+    AttributeValue::String(text) => match parse_attribute_value(key, text, None) {
+      Ok(AttributeValue::Date(_)) => format!("!string {}", quote_text(text)),
+      _ => quote_text(text),
+    },
+    value => value.to_string(),
+  }
+}
+
+fn parse_text_value(text: &str) -> Result<TextValue, String> {
+  let text = text.trim();
+
+  if text.starts_with('"') {
+    return serde_json::from_str::<String>(text)
+      .map(|text| TextValue {
+        text,
+        is_quoted: true,
+      })
+      .map_err(|err| format!("invalid quoted string {}: {}", text, err));
+  }
+
+  if text.starts_with('\'') {
+    if text.len() < 2 || !text.ends_with('\'') {
+      return Err(format!("unclosed quoted string {}", text));
+    }
+
+    return Ok(TextValue {
+      text: text[1..text.len() - 1].replace("''", "'"),
+      is_quoted: true,
+    });
+  }
+
+  Ok(TextValue {
+    text: text.to_string(),
+    is_quoted: false,
+  })
+}
+
+// Without a type in the schema the quoted values are strings, and the rest
+// are inferred
+fn parse_exchange_attribute(
+  key: &str,
+  value: &TextValue,
+  schema: &AttributesSchema,
+) -> OResult<(String, AttributeValue)> {
+  let key = parse_attribute_key(key)?;
+  let attribute_type = schema.get(&key).cloned().or(if value.is_quoted {
+    Some(AttributeType::String)
+  } else {
+    None
+  });
+  let value = parse_attribute_value(&key, &value.text, attribute_type)?;
+
+  Ok((key, value))
+}
+
+fn parse_exchange_number(field: &str, value: &TextValue) -> Result<Option<usize>, String> {
+  if value.text.is_empty() {
+    return Ok(None);
+  }
+
+  value
+    .text
+    .parse::<usize>()
+    .map(Some)
+    .map_err(|_| format!("{} must be a positive number, found: {}", field, value.text))
+}
+
+fn parse_exchange_tags(tags: &str) -> Vec<String> {
+  tags.split(',').map(|tag| tag.to_string()).collect()
+}
+
+// The records keep the ids of the file. The missing ones are taken from the
+// items or locations with the same name, or they are new.
+fn get_drafts_records(drafts: Vec<RecordDraft>) -> OResult<Vec<Record>> {
+  let mut what_names: HashSet<&str> = HashSet::new();
+
+  for draft in &drafts {
+    if draft.what.trim().is_empty() || draft.location.trim().is_empty() {
+      return Err(OError::InvalidArgument(
+        "Every imported item needs a what and a location".to_string(),
+      ));
+    }
+
+    if !what_names.insert(&draft.what) {
+      return Err(OError::DuplicateWhat(draft.what.clone()));
+    }
+  }
+
+  let mut next_id = drafts
+    .iter()
+    .flat_map(|draft| draft.what_id.iter().chain(draft.location_id.iter()))
+    .max()
+    .map_or(0, |id| id + 1);
+  let mut names_ids: HashMap<String, usize> = HashMap::new();
+
+  for draft in &drafts {
+    if let Some(what_id) = draft.what_id {
+      names_ids.insert(draft.what.clone(), what_id);
+    }
+  }
+
+  for draft in &drafts {
+    if let Some(location_id) = draft.location_id {
+      names_ids
+        .entry(draft.location.clone())
+        .or_insert(location_id);
+    }
+  }
+
+  let mut get_id = |name: &str| {
+    *names_ids.entry(name.to_string()).or_insert_with(|| {
+      next_id += 1;
+      next_id - 1
+    })
+  };
+  let records: Vec<Record> = drafts
+    .into_iter()
+    .map(|draft| {
+      let what_id = draft.what_id.unwrap_or_else(|| get_id(&draft.what));
+      let location_id = draft.location_id.unwrap_or_else(|| get_id(&draft.location));
+      // the missing dates are the time of the import
+      let updated = parse_import_date(&draft.what, "updated", draft.updated.as_deref())?
+        .unwrap_or_else(get_now_date);
+      let created = parse_import_date(&draft.what, "created", draft.created.as_deref())?
+        .unwrap_or_else(|| updated.clone());
+
+      Ok(Record {
+        what_id,
+        location_id,
+        what: draft.what,
+        location: draft.location,
+        created,
+        updated,
+        notes: draft
+          .notes
+          .filter(|notes| !notes.is_empty())
+          .unwrap_or_else(get_empty_notes_text),
+        tags: draft
+          .tags
+          .iter()
+          .map(|tag| tag.trim().to_lowercase())
+          .filter(|tag| !tag.is_empty())
+          .collect(),
+        attributes: draft.attributes,
+        quantity: draft.quantity,
+        min_quantity: draft.min_quantity,
+      })
+    })
+    .collect::<OResult<Vec<Record>>>()?;
+
+  let mut ids_names: HashMap<usize, &str> = HashMap::new();
+
+  for record in &records {
+    if ids_names.insert(record.what_id, &record.what).is_some() {
+      return Err(OError::InvalidArgument(format!(
+        "The id {} is used by several items",
+        record.what_id
+      )));
+    }
+  }
+
+  for record in &records {
+    let name = ids_names
+      .entry(record.location_id)
+      .or_insert(&record.location);
+
+    if *name != record.location {
+      return Err(OError::InvalidArgument(format!(
+        "The id {} is used by {} and {}",
+        record.location_id, name, record.location
+      )));
+    }
+  }
+
+  Ok(records)
+}
+
+fn format_jsonl(records: &[Record]) -> OResult<String> {
+  let mut contents = String::new();
+
+  for record in records {
+    let line =
+      serde_json::to_string(record).map_err(|err| OError::InvalidArgument(err.to_string()))?;
+
+    contents.push_str(&line);
+    contents.push('\n');
+  }
+
+  Ok(contents)
+}
+
+fn parse_jsonl(contents: &str) -> Result<Vec<RecordDraft>, String> {
+  contents
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty())
+    .map(|(idx, line)| serde_json::from_str(line).map_err(|err| get_line_error(idx + 1, err)))
+    .collect()
+}
+
+fn format_yaml(records: &[Record]) -> String {
+  if records.is_empty() {
+    return "[]\n".to_string();
+  }
+
+  let mut lines: Vec<String> = vec![];
+
+  for record in records {
+    lines.push(format!("- what_id: {}", record.what_id));
+    lines.push(format!("  what: {}", format_yaml_text(&record.what)));
+    lines.push(format!("  location_id: {}", record.location_id));
+    lines.push(format!(
+      "  location: {}",
+      format_yaml_text(&record.location)
+    ));
+    lines.push(format!("  created: {}", format_yaml_text(&record.created)));
+    lines.push(format!("  updated: {}", format_yaml_text(&record.updated)));
+    lines.push(format!("  notes: {}", format_yaml_text(&record.notes)));
+
+    if !record.tags.is_empty() {
+      let tags: Vec<String> = record
+        .tags
+        .iter()
+        .map(|tag| format_yaml_text(tag))
+        .collect();
+
+      lines.push(format!("  tags: [{}]", tags.join(", ")));
+    }
+
+    if let Some(quantity) = record.quantity {
+      lines.push(format!("  quantity: {}", quantity));
+    }
+
+    if let Some(min_quantity) = record.min_quantity {
+      lines.push(format!("  min_quantity: {}", min_quantity));
+    }
+
+    if !record.attributes.is_empty() {
+      lines.push("  attributes:".to_string());
+
+      for (key, value) in &record.attributes {
+        lines.push(format!(
+          "    {}: {}",
+          key,
+          format_yaml_attribute(key, value)
+        ));
+      }
+    }
+  }
+
+  lines.join("\n") + "\n"
+}
+
+// YAML types the plain numbers and bools, the dates are inferred from the
+// strings. The export tags the strings that look like dates with `!string`.
+fn parse_yaml_attribute(
+  key: &str,
+  value: Value,
+  schema: &AttributesSchema,
+) -> OResult<Option<(String, AttributeValue)>> {
+  let key = parse_attribute_key(key)?;
+  let (text, value_type) = match value {
+    Value::Null => return Ok(None),
+    Value::Bool(flag) => (flag.to_string(), AttributeType::Bool),
+    Value::Number(number) => (number.to_string(), AttributeType::Number),
+    Value::String(text) => match parse_attribute_value(&key, &text, None)? {
+      AttributeValue::Date(_) => (text, AttributeType::Date),
+      _ => (text, AttributeType::String),
+    },
+    Value::Tagged(tagged) => match (tagged.tag == Tag::new("string"), tagged.value) {
+      (true, Value::String(text)) => (text, AttributeType::String),
+      (_, value) => {
+        return Err(OError::InvalidArgument(format!(
+          "The attribute {} has an unsupported tag {}, found: {:?}",
+          key, tagged.tag, value
+        )))
+      }
+    },
+    Value::Sequence(_) | Value::Mapping(_) => {
+      return Err(OError::InvalidArgument(format!(
+        "The attribute {} must be a single value",
+        key
+      )))
+    }
+  };
+  let value = parse_attribute_value(&key, &text, schema.get(&key).cloned().or(Some(value_type)))?;
+
+  Ok(Some((key, value)))
+}
+
+// The attributes are taken out of each item, they need the schema, and the
+// rest of the fields are read by serde
+fn parse_yaml_draft(mut item: Value, schema: &AttributesSchema) -> Result<RecordDraft, String> {
+  let attributes = item
+    .as_mapping_mut()
+    .and_then(|mapping| mapping.remove("attributes"));
+  let mut draft: RecordDraft = serde_yaml::from_value(item).map_err(|err| err.to_string())?;
+  let attributes = match attributes {
+    None | Some(Value::Null) => Mapping::new(),
+    Some(Value::Mapping(attributes)) => attributes,
+    Some(_) => return Err("attributes must be a mapping of keys to values".to_string()),
+  };
+
+  for (key, value) in attributes {
+    let key = match key {
+      Value::String(key) => key,
+      key => {
+        return Err(format!(
+          "the attribute keys must be texts, found: {:?}",
+          key
+        ))
+      }
+    };
+
+    if let Some((key, value)) =
+      parse_yaml_attribute(&key, value, schema).map_err(|err| err.to_string())?
+    {
+      draft.attributes.insert(key, value);
+    }
+  }
+
+  Ok(draft)
+}
+
+fn parse_yaml(contents: &str, schema: &AttributesSchema) -> Result<Vec<RecordDraft>, String> {
+  // an empty file has no items
+  let items: Option<Vec<Value>> = serde_yaml::from_str(contents).map_err(|err| err.to_string())?;
+
+  items
+    .unwrap_or_default()
+    .into_iter()
+    .enumerate()
+    .map(|(idx, item)| {
+      parse_yaml_draft(item, schema).map_err(|err| format!("item {}: {}", idx + 1, err))
+    })
+    .collect()
+}
+
+// The same hierarchy of `o tree`, with the fields of each item in the lines
+// below it. The items in cycles can't be placed, so they are an error.
+fn format_markdown(records: &[Record]) -> OResult<String> {
+  fn format_item(
+    item: &TreeItem,
+    depth: usize,
+    records: &[Record],
+    context: &Context,
+    lines: &mut Vec<String>,
+    written: &mut usize,
+  ) {
+    lines.push(format!(
+      "{}- {} [{}]",
+      "  ".repeat(depth),
+      item.name,
+      item.id
+    ));
+
+    if let Some(record_idx) = context.id_to_record_idx_map.get(&item.id) {
+      let record = &records[*record_idx];
+      let indent = "  ".repeat(depth + 1);
+      let format_text = |text: &str| {
+        if get_is_plain_markdown(text) {
+          text.to_string()
+        } else {
+          quote_text(text)
+        }
+      };
+
+      *written += 1;
+
+      if record.notes != get_empty_notes_text() {
+        lines.push(format!("{}notes: {}", indent, format_text(&record.notes)));
+      }
+
+      if !record.tags.is_empty() {
+        lines.push(format!("{}tags: {}", indent, record.tags.join(", ")));
+      }
+
+      if let Some(quantity) = record.quantity {
+        lines.push(format!("{}quantity: {}", indent, quantity));
+      }
+
+      if let Some(min_quantity) = record.min_quantity {
+        lines.push(format!("{}min_quantity: {}", indent, min_quantity));
+      }
+
+      for (key, value) in &record.attributes {
+        lines.push(format!(
+          "{}{}: {}",
+          indent,
+          key,
+          format_attribute_value(key, value, get_is_plain_markdown)
+        ));
+      }
+
+      lines.push(format!("{}created: {}", indent, record.created));
+      lines.push(format!("{}updated: {}", indent, record.updated));
+    }
+
+    for child in &item.children {
+      format_item(child, depth + 1, records, context, lines, written);
+    }
+  }
+
+  let store = Store::from_records(records.to_vec());
+  let context = store.context();
+  let mut lines: Vec<String> = vec![];
+  let mut written = 0;
+
+  for item in store.tree(&TreeOptions::default())? {
+    format_item(&item, 0, records, &context, &mut lines, &mut written);
+  }
+
+  if written != records.len() {
+    return Err(OError::InvalidArgument(
+      "Some items are inside themselves and can't be placed in the outline, run `o check`"
+        .to_string(),
+    ));
+  }
+
+  Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+}
+
+// `name [id]`, the id is optional
+fn parse_outline_name(text: &str) -> (String, Option<usize>) {
+  if let (Some(start), true) = (text.rfind('['), text.ends_with(']')) {
+    if let Ok(id) = text[start + 1..text.len() - 1].parse::<usize>() {
+      return (text[..start].trim().to_string(), Some(id));
+    }
+  }
+
+  (text.trim().to_string(), None)
+}
+
+fn parse_outline_draft(
+  item: &OutlineItem,
+  parent: &OutlineItem,
+  schema: &AttributesSchema,
+) -> Result<RecordDraft, String> {
+  let mut draft = RecordDraft {
+    what_id: item.id,
+    what: item.name.clone(),
+    location_id: parent.id,
+    location: parent.name.clone(),
+    ..RecordDraft::default()
+  };
+
+  for (key, value, line) in &item.details {
+    let get_number = || parse_exchange_number(key, value).map_err(|err| get_line_error(*line, err));
+
+    match key.as_str() {
+      "notes" => draft.notes = Some(value.text.clone()),
+      "tags" => draft.tags = parse_exchange_tags(&value.text),
+      "quantity" => draft.quantity = get_number()?,
+      "min_quantity" => draft.min_quantity = get_number()?,
+      "created" => draft.created = Some(value.text.clone()),
+      "updated" => draft.updated = Some(value.text.clone()),
+      _ => {
+        let (key, value) =
+          parse_exchange_attribute(key, value, schema).map_err(|err| get_line_error(*line, err))?;
+
+        draft.attributes.insert(key, value);
+      }
+    }
+  }
+
+  Ok(draft)
+}
+
+// Nested bullets with `-`, `*` or `+`, the headings are skipped
+fn parse_markdown(contents: &str, schema: &AttributesSchema) -> Result<Vec<RecordDraft>, String> {
+  let mut items: Vec<OutlineItem> = vec![];
+  let mut parents: Vec<usize> = vec![];
+
+  for (idx, line) in contents.lines().enumerate() {
+    let text = line.trim();
+    let indent: usize = line
+      .chars()
+      .take_while(|c| c.is_whitespace())
+      .map(|c| if c == '\t' { 4 } else { 1 })
+      .sum();
+
+    if text.is_empty() || text.starts_with('#') {
+      continue;
+    }
+
+    let bullet = ["- ", "* ", "+ "]
+      .iter()
+      .find_map(|marker| text.strip_prefix(marker));
+
+    if let Some(bullet) = bullet {
+      let (name, id) = parse_outline_name(bullet);
+
+      if name.is_empty() {
+        return Err(get_line_error(idx + 1, "the item has no name"));
+      }
+
+      while parents
+        .last()
+        .is_some_and(|parent| items[*parent].indent >= indent)
+      {
+        parents.pop();
+      }
+
+      items.push(OutlineItem {
+        name,
+        id,
+        indent,
+        parent: parents.last().cloned(),
+        details: vec![],
+      });
+      parents.push(items.len() - 1);
+
+      continue;
+    }
+
+    let (key, value) = match text.split_once(':') {
+      Some((key, value)) => (key.trim().to_lowercase(), parse_text_value(value)),
+      None => return Err(get_line_error(idx + 1, "expected an item or key: value")),
+    };
+    let value = value.map_err(|err| get_line_error(idx + 1, err))?;
+
+    match items.last_mut() {
+      Some(item) if item.parent.is_some() => item.details.push((key, value, idx + 1)),
+      _ => {
+        return Err(get_line_error(
+          idx + 1,
+          "the details must be below an item, the top-level ones are locations",
+        ))
+      }
+    }
+  }
+
+  items
+    .iter()
+    .filter_map(|item| item.parent.map(|parent| (item, &items[parent])))
+    .map(|(item, parent)| parse_outline_draft(item, parent, schema))
+    .collect()
+}
+
+fn quote_dot(text: &str) -> String {
+  format!(
+    "\"{}\"",
+    text
+      .replace('\\', "\\\\")
+      .replace('"', "\\\"")
+      .replace('\n', "\\n")
+  )
+}
+
+fn quote_mermaid(text: &str) -> String {
+  format!(
+    "\"{}\"",
+    text
+      .replace('"', "#quot;")
+      .replace('<', "#lt;")
+      .replace('>', "#gt;")
+      .replace('\n', "<br>")
+  )
+}
+
+// The name and id like in `o tree`, with the chosen labels below
+fn get_graph_label(item: &TreeItem, record: Option<&Record>, labels: &[GraphLabel]) -> String {
+  let mut lines = vec![format!("{} [{}]", item.name, item.id)];
+
+  for label in labels {
+    match (label, record) {
+      (GraphLabel::Count, _) if item.count > 0 => lines.push(format!("<{} items>", item.count)),
+      (GraphLabel::Notes, Some(record)) if record.notes != get_empty_notes_text() => {
+        lines.push(record.notes.clone())
+      }
+      _ => {}
+    }
+  }
+
+  lines.join("\n")
+}
+
+// The nodes with children are clusters (subgraphs in Mermaid) with their
+// children inside, and the rest are the leaves. The node ids are the item ids.
+fn format_graph(
+  records: &[Record],
+  format: ExchangeFormat,
+  options: &ExportOptions,
+) -> OResult<String> {
+  fn format_item(
+    item: &TreeItem,
+    depth: usize,
+    format: ExchangeFormat,
+    records: &[Record],
+    context: &Context,
+    options: &ExportOptions,
+    lines: &mut Vec<String>,
+  ) {
+    let indent = "  ".repeat(depth);
+    let record = context
+      .id_to_record_idx_map
+      .get(&item.id)
+      .map(|record_idx| &records[*record_idx]);
+    let label = get_graph_label(item, record, &options.labels);
+
+    if item.children.is_empty() {
+      match format {
+        ExchangeFormat::Dot => lines.push(format!(
+          "{}n{} [label={}];",
+          indent,
+          item.id,
+          quote_dot(&label)
+        )),
+        _ => lines.push(format!("{}n{}[{}]", indent, item.id, quote_mermaid(&label))),
+      }
+
+      return;
+    }
+
+    match format {
+      ExchangeFormat::Dot => {
+        lines.push(format!("{}subgraph cluster_{} {{", indent, item.id));
+        lines.push(format!("{}  label={};", indent, quote_dot(&label)));
+      }
+      _ => lines.push(format!(
+        "{}subgraph n{} [{}]",
+        indent,
+        item.id,
+        quote_mermaid(&label)
+      )),
+    }
+
+    for child in &item.children {
+      format_item(child, depth + 1, format, records, context, options, lines);
+    }
+
+    match format {
+      ExchangeFormat::Dot => lines.push(format!("{}}}", indent)),
+      _ => lines.push(format!("{}end", indent)),
+    }
+  }
+
+  let store = Store::from_records(records.to_vec());
+  let context = store.context();
+  let items = store.tree(&TreeOptions {
+    root: options.root,
+    ..TreeOptions::default()
+  })?;
+  let mut lines: Vec<String> = match format {
+    ExchangeFormat::Dot => vec!["digraph o {".to_string(), "  node [shape=box];".to_string()],
+    _ => vec!["flowchart TB".to_string()],
+  };
+
+  for item in &items {
+    format_item(item, 1, format, records, &context, options, &mut lines);
+  }
+
+  if format == ExchangeFormat::Dot {
+    lines.push("}".to_string());
+  }
+
+  Ok(lines.iter().map(|line| format!("{}\n", line)).collect())
+}
+
+pub fn format_records(
+  records: &[Record],
+  format: ExchangeFormat,
+  options: &ExportOptions,
+) -> OResult<String> {
+  let has_graph_options = options.root.is_some() || !options.labels.is_empty();
+
+  if has_graph_options && !format.get_is_graph() {
+    return Err(OError::InvalidArgument(
+      "The root and the labels can only be used with the dot and mermaid formats".to_string(),
+    ));
+  }
+
+  match format {
+    ExchangeFormat::Jsonl => format_jsonl(records),
+    ExchangeFormat::Yaml => Ok(format_yaml(records)),
+    ExchangeFormat::Markdown => format_markdown(records),
+    ExchangeFormat::Dot | ExchangeFormat::Mermaid => format_graph(records, format, options),
+  }
+}
+
+// The attributes without a type in `schema` are inferred, unless they are
+// quoted (in YAML, unless they are quoted strings that don't look like dates)
+pub fn read_records_file(
+  file_path: &str,
+  format: ExchangeFormat,
+  schema: &AttributesSchema,
+) -> OResult<Vec<Record>> {
+  let contents = read_to_string(file_path).map_err(|err| get_file_error(file_path, err))?;
+  let drafts = match format {
+    ExchangeFormat::Jsonl => parse_jsonl(&contents),
+    ExchangeFormat::Yaml => parse_yaml(&contents, schema),
+    ExchangeFormat::Markdown => parse_markdown(&contents, schema),
+    ExchangeFormat::Dot | ExchangeFormat::Mermaid => {
+      return Err(OError::InvalidArgument(
+        "The dot and mermaid formats can only be exported".to_string(),
+      ));
+    }
+  }
+  .map_err(|err| get_file_error(file_path, err))?;
+
+  get_drafts_records(drafts)
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{DateTime, NaiveDate};
+
+  use super::*;
+  use crate::data::parse_record_date;
+
+  const DATE: &str = "2024-01-02T03:04:05+00:00";
+
+  // Texts that need quotes in some of the formats
+  const TRICKY_TEXTS: &[&str] = &[
+    "true",
+    "123",
+    "1e3",
+    "null",
+    "a: b",
+    "#hash",
+    "'q'",
+    "\"q\"",
+    " padded ",
+    "two\nlines\n",
+    "ünïcödé 箱 📦",
+    "- dash",
+    "[x], {y}",
+  ];
+
+  fn get_record(what_id: usize, what: &str, location_id: usize, location: &str) -> Record {
+    Record {
+      what_id,
+      what: what.to_string(),
+      location_id,
+      location: location.to_string(),
+      created: DATE.to_string(),
+      updated: DATE.to_string(),
+      notes: get_empty_notes_text(),
+      tags: vec![],
+      attributes: Attributes::new(),
+      quantity: None,
+      min_quantity: None,
+    }
+  }
+
+  fn get_tricky_records() -> Vec<Record> {
+    let mut records = vec![get_record(1, "box", 0, "shelf ✓")];
+
+    for (idx, text) in TRICKY_TEXTS.iter().enumerate() {
+      let mut record = get_record(idx + 2, &format!("item {} ñ", idx), 1, "box");
+
+      record.notes = text.to_string();
+      record.tags = vec!["tag".to_string(), "ü".to_string()];
+      record.quantity = Some(idx);
+      record
+        .attributes
+        .insert("text".to_string(), AttributeValue::String(text.to_string()));
+      record
+        .attributes
+        .insert("price".to_string(), AttributeValue::Number(20.5));
+      record.attributes.insert(
+        "bought".to_string(),
+        AttributeValue::Date("2024-01-31".to_string()),
+      );
+      record
+        .attributes
+        .insert("ok".to_string(), AttributeValue::Bool(false));
+      record.attributes.insert(
+        "serial".to_string(),
+        AttributeValue::String("2024-01-31".to_string()),
+      );
+      records.push(record);
+    }
+
+    records
+  }
+
+  fn get_sorted_json(records: &[Record]) -> serde_json::Value {
+    let mut records = records.to_vec();
+
+    records.sort_by_key(|record| record.what_id);
+
+    serde_json::to_value(&records).unwrap()
+  }
+
+  fn get_round_trip(records: &[Record], format: ExchangeFormat) -> Vec<Record> {
+    let contents = format_records(records, format, &ExportOptions::default()).unwrap();
+    let drafts = match format {
+      ExchangeFormat::Jsonl => parse_jsonl(&contents),
+      ExchangeFormat::Yaml => parse_yaml(&contents, &AttributesSchema::new()),
+      _ => parse_markdown(&contents, &AttributesSchema::new()),
+    }
+    .unwrap_or_else(|err| panic!("{}\n{}", err, contents));
+
+    get_drafts_records(drafts).unwrap()
+  }
+
+  fn parse_yaml_records(contents: &str) -> Vec<Record> {
+    get_drafts_records(parse_yaml(contents, &AttributesSchema::new()).unwrap()).unwrap()
+  }
+
+  #[test]
+  fn test_round_trips() {
+    let records = get_tricky_records();
+
+    for format in &[
+      ExchangeFormat::Jsonl,
+      ExchangeFormat::Yaml,
+      ExchangeFormat::Markdown,
+    ] {
+      assert_eq!(
+        get_sorted_json(&get_round_trip(&records, *format)),
+        get_sorted_json(&records),
+        "{:?}",
+        format
+      );
+    }
+  }
+
+  #[test]
+  fn test_markdown_quotes_leading_quotes() {
+    let mut records = vec![get_record(1, "box", 0, "shelf")];
+
+    records[0].notes = "'q'".to_string();
+
+    let contents = format_markdown(&records).unwrap();
+
+    assert!(contents.contains("notes: \"'q'\""), "{}", contents);
+    assert_eq!(
+      get_round_trip(&records, ExchangeFormat::Markdown)[0].notes,
+      "'q'"
+    );
+  }
+
+  #[test]
+  fn test_yaml_hand_edited() {
+    let records = parse_yaml_records(
+      "---
+# the garage
+- what: drill # the big one
+  location: 'garage ''A'''
+  notes: |
+    first line
+      indented # not a comment
+
+    after a blank line
+  tags:
+  - Tools
+  - \"power, tools\"
+  attributes: {price: 12, serial: '00123', 'label': 箱}
+-
+  what: saw
+  location: garage 'A'
+  notes: >-
+    folded
+    text
+
+    paragraph
+  tags: [a, 'b']
+- what: \"tape \\u00e9\"
+  location: saw
+  notes: |-
+    kept
+  quantity: 3
+",
+    );
+
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].location, "garage 'A'");
+    assert_eq!(
+      records[0].notes,
+      "first line\n  indented # not a comment\n\nafter a blank line\n"
+    );
+    assert_eq!(records[0].tags, vec!["tools", "power, tools"]);
+    assert_eq!(
+      records[0].attributes.get("serial"),
+      Some(&AttributeValue::String("00123".to_string()))
+    );
+    assert_eq!(
+      records[0].attributes.get("price"),
+      Some(&AttributeValue::Number(12.0))
+    );
+    assert_eq!(
+      records[0].attributes.get("label"),
+      Some(&AttributeValue::String("箱".to_string()))
+    );
+    assert_eq!(records[1].notes, "folded text\nparagraph");
+    assert_eq!(records[1].tags, vec!["a", "b"]);
+    assert_eq!(records[1].location_id, records[0].location_id);
+    assert_eq!(records[2].what, "tape é");
+    assert_eq!(records[2].notes, "kept");
+    assert_eq!(records[2].location_id, records[1].what_id);
+    assert_eq!(records[2].quantity, Some(3));
+  }
+
+  #[test]
+  fn test_yaml_block_scalar_chomping() {
+    let get_notes = |header: &str| {
+      let contents = format!(
+        "- what: a\n  location: b\n  notes: {}\n    text\n\n\n- what: c\n  location: b\n",
+        header
+      );
+
+      parse_yaml_records(&contents)[0].notes.clone()
+    };
+
+    assert_eq!(get_notes("|"), "text\n");
+    assert_eq!(get_notes("|-"), "text");
+    assert_eq!(get_notes("|+"), "text\n\n\n");
+    assert_eq!(get_notes(">"), "text\n");
+  }
+
+  #[test]
+  fn test_yaml_wrapped_anchors_and_flows() {
+    let records = parse_yaml_records(
+      "- what: drill
+  location: &garage garage
+  notes: a note that wraps
+    onto a second line
+  tags: &tags [tools, power]
+  attributes:
+    price: 12.50
+    bought: 2024-01-31
+    serial: !string 2024-01-31
+    code: \"19.5\"
+- {what: saw, location: *garage, tags: *tags, attributes: {ok: true, sku: 00123}}
+",
+    );
+    let attributes = &records[0].attributes;
+
+    assert_eq!(records[0].notes, "a note that wraps onto a second line");
+    assert_eq!(records[1].location, "garage");
+    assert_eq!(records[1].tags, vec!["tools", "power"]);
+    assert_eq!(attributes.get("price"), Some(&AttributeValue::Number(12.5)));
+    assert_eq!(
+      attributes.get("bought"),
+      Some(&AttributeValue::Date("2024-01-31".to_string()))
+    );
+    assert_eq!(
+      attributes.get("serial"),
+      Some(&AttributeValue::String("2024-01-31".to_string()))
+    );
+    assert_eq!(
+      attributes.get("code"),
+      Some(&AttributeValue::String("19.5".to_string()))
+    );
+    assert_eq!(
+      records[1].attributes.get("sku"),
+      Some(&AttributeValue::String("00123".to_string()))
+    );
+    assert_eq!(
+      records[1].attributes.get("ok"),
+      Some(&AttributeValue::Bool(true))
+    );
+  }
+
+  #[test]
+  fn test_import_dates() {
+    let get_drafts = |created: &str| {
+      parse_yaml(
+        &format!("- what: a\n  location: b\n  created: {}\n", created),
+        &AttributesSchema::new(),
+      )
+      .unwrap()
+    };
+    let records = get_drafts_records(get_drafts("2024-01-01")).unwrap();
+
+    assert!(DateTime::parse_from_rfc3339(&records[0].created).is_ok());
+    assert_eq!(
+      parse_record_date(&records[0].created),
+      NaiveDate::from_ymd_opt(2024, 1, 1)
+    );
+    assert_eq!(
+      get_drafts_records(get_drafts(DATE)).unwrap()[0].created,
+      DATE
+    );
+    assert!(get_drafts_records(get_drafts("31/01/24")).is_ok());
+    assert!(get_drafts_records(get_drafts("yesterday")).is_err());
+    assert!(get_drafts_records(get_drafts("2024-02-30")).is_err());
+  }
+
+  #[test]
+  fn test_yaml_errors() {
+    let get_error = |contents: &str| parse_yaml(contents, &AttributesSchema::new()).unwrap_err();
+
+    assert!(get_error("- what: a\n  location: b\n   notes: c\n").contains("line 3"));
+    assert!(get_error("- what: a\n  colour: red\n").starts_with("item 1: unknown field `colour`"));
+    assert!(get_error("- what: \"a\n").contains("line 1"));
+    assert!(get_error("- what: a\n  location: b\n  quantity: x\n").starts_with("item 1:"));
+    assert!(
+      get_error("- what: a\n  location: b\n  attributes: {price: .nan}\n")
+        .contains("must be a number")
+    );
+  }
+
+  #[test]
+  fn test_markdown_without_ids() {
+    let drafts = parse_markdown(
+      "# Garage
+
+- garage
+  - shelf [2]
+    - drill
+      notes: needs \"bits\"
+      tags: tools
+    * screws
+      quantity: 40
+  + box
+",
+      &AttributesSchema::new(),
+    )
+    .unwrap();
+    let records = get_drafts_records(drafts).unwrap();
+    let get_by_name = |name: &str| records.iter().find(|record| record.what == name).unwrap();
+
+    assert_eq!(records.len(), 4);
+    assert_eq!(get_by_name("shelf").what_id, 2);
+    assert_eq!(get_by_name("drill").location_id, 2);
+    assert_eq!(get_by_name("drill").notes, "needs \"bits\"");
+    assert_eq!(get_by_name("screws").quantity, Some(40));
+    assert_eq!(
+      get_by_name("box").location_id,
+      get_by_name("shelf").location_id
+    );
+  }
+}
@@ -0,0 +1,61 @@
+// Below this similarity a field doesn't count as a match
+pub const MIN_SIMILARITY: f64 = 0.6;
+
+fn get_edit_distance(a: &[char], b: &[char]) -> usize {
+  let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+
+  for (a_idx, a_char) in a.iter().enumerate() {
+    let mut current_row = vec![a_idx + 1];
+
+    for (b_idx, b_char) in b.iter().enumerate() {
+      let cost = if a_char == b_char { 0 } else { 1 };
+
+      current_row.push(
+        (previous_row[b_idx] + cost)
+          .min(previous_row[b_idx + 1] + 1)
+          .min(current_row[b_idx] + 1),
+      );
+    }
+
+    previous_row = current_row;
+  }
+
+  previous_row[b.len()]
+}
+
+fn get_words_similarity(a: &str, b: &str) -> f64 {
+  let a: Vec<char> = a.chars().collect();
+  let b: Vec<char> = b.chars().collect();
+  let max_len = a.len().max(b.len());
+
+  if max_len == 0 {
+    return 0.0;
+  }
+
+  1.0 - get_edit_distance(&a, &b) as f64 / max_len as f64
+}
+
+// From 0 to 1: exact words score 1, contained texts 0.9 and the rest
+// depends on the edit distance with the closest word (or the whole text)
+pub fn get_similarity(query: &str, text: &str) -> f64 {
+  let query_l = query.to_lowercase();
+  let text_l = text.to_lowercase();
+
+  if query_l.is_empty() {
+    return 0.0;
+  }
+
+  if text_l == query_l || text_l.split_whitespace().any(|word| word == query_l) {
+    return 1.0;
+  }
+
+  if text_l.contains(&query_l) {
+    return 0.9;
+  }
+
+  text_l
+    .split_whitespace()
+    .chain(std::iter::once(text_l.as_str()))
+    .map(|word| get_words_similarity(&query_l, word) * 0.9)
+    .fold(0.0, f64::max)
+}
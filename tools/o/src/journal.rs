@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+
+use chrono::Local;
+
+use crate::data::{get_changed_fields, migrate_record_dates, Record};
+use crate::errors::{OError, OResult};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordVersion {
+  pub idx: usize,
+  pub record: Record,
+}
+
+// `before` is missing for inserted records and `after` for removed ones
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RecordChange {
+  pub before: Option<RecordVersion>,
+  pub after: Option<RecordVersion>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct JournalEntry {
+  pub command: String,
+  pub timestamp: String,
+  pub changes: Vec<RecordChange>,
+}
+
+// `position` is the number of entries applied to o_data, the entries after
+// it were undone and can be redone
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Journal {
+  pub entries: Vec<JournalEntry>,
+  pub position: usize,
+}
+
+pub fn diff_records(old_records: &[Record], new_records: &[Record]) -> Vec<RecordChange> {
+  let old_ids: HashMap<usize, usize> = old_records
+    .iter()
+    .enumerate()
+    .map(|(idx, record)| (record.what_id, idx))
+    .collect();
+  let new_ids: HashMap<usize, usize> = new_records
+    .iter()
+    .enumerate()
+    .map(|(idx, record)| (record.what_id, idx))
+    .collect();
+
+  // with repeated ids the records can't be matched, so all of them are replaced
+  if old_ids.len() != old_records.len() || new_ids.len() != new_records.len() {
+    let removed = old_records
+      .iter()
+      .enumerate()
+      .map(|(idx, record)| RecordChange {
+        before: Some(RecordVersion {
+          idx,
+          record: record.clone(),
+        }),
+        after: None,
+      });
+    let inserted = new_records
+      .iter()
+      .enumerate()
+      .map(|(idx, record)| RecordChange {
+        before: None,
+        after: Some(RecordVersion {
+          idx,
+          record: record.clone(),
+        }),
+      });
+
+    return removed.chain(inserted).collect();
+  }
+
+  let get_is_unchanged = |record: &&Record| match old_ids.get(&record.what_id) {
+    None => false,
+    Some(old_idx) => match new_ids.get(&record.what_id) {
+      None => false,
+      Some(new_idx) => {
+        get_changed_fields(&old_records[*old_idx], &new_records[*new_idx]).is_empty()
+      }
+    },
+  };
+  let old_unchanged_ids: Vec<usize> = old_records
+    .iter()
+    .filter(get_is_unchanged)
+    .map(|record| record.what_id)
+    .collect();
+  let new_unchanged_ids: Vec<usize> = new_records
+    .iter()
+    .filter(get_is_unchanged)
+    .map(|record| record.what_id)
+    .collect();
+  // when the records were reordered all of them are stored as changed
+  let is_reordered = old_unchanged_ids != new_unchanged_ids;
+  let mut changes: Vec<RecordChange> = vec![];
+
+  for (old_idx, old_record) in old_records.iter().enumerate() {
+    let before = Some(RecordVersion {
+      idx: old_idx,
+      record: old_record.clone(),
+    });
+
+    match new_ids.get(&old_record.what_id) {
+      None => changes.push(RecordChange {
+        before,
+        after: None,
+      }),
+      Some(new_idx) => {
+        let new_record = &new_records[*new_idx];
+
+        if is_reordered || !get_changed_fields(old_record, new_record).is_empty() {
+          changes.push(RecordChange {
+            before,
+            after: Some(RecordVersion {
+              idx: *new_idx,
+              record: new_record.clone(),
+            }),
+          });
+        }
+      }
+    }
+  }
+
+  for (new_idx, new_record) in new_records.iter().enumerate() {
+    if !old_ids.contains_key(&new_record.what_id) {
+      changes.push(RecordChange {
+        before: None,
+        after: Some(RecordVersion {
+          idx: new_idx,
+          record: new_record.clone(),
+        }),
+      });
+    }
+  }
+
+  changes
+}
+
+// The records that didn't change keep their relative order, so removing the
+// `from` versions and inserting the `to` versions by index is exact
+fn apply_versions(
+  records: &[Record],
+  from_versions: Vec<&RecordVersion>,
+  to_versions: Vec<&RecordVersion>,
+) -> OResult<Vec<Record>> {
+  let mut records = records.to_vec();
+  let mut from_versions = from_versions;
+  let mut to_versions = to_versions;
+
+  from_versions.sort_by_key(|version| version.idx);
+  to_versions.sort_by_key(|version| version.idx);
+
+  for version in from_versions.iter().rev() {
+    match records.get(version.idx) {
+      Some(record) if record.what_id == version.record.what_id => {
+        records.remove(version.idx);
+      }
+      _ => {
+        return Err(OError::CorruptData(
+          "the journal doesn't match o_data, it was probably modified by hand".to_string(),
+        ));
+      }
+    }
+  }
+
+  for version in to_versions {
+    if version.idx > records.len() {
+      return Err(OError::CorruptData(
+        "the journal doesn't match o_data, it was probably modified by hand".to_string(),
+      ));
+    }
+
+    records.insert(version.idx, version.record.clone());
+  }
+
+  Ok(records)
+}
+
+impl JournalEntry {
+  pub fn new(command: &str, changes: Vec<RecordChange>) -> Self {
+    Self {
+      command: command.to_string(),
+      timestamp: Local::now().to_rfc3339(),
+      changes,
+    }
+  }
+
+  pub fn undo(&self, records: &[Record]) -> OResult<Vec<Record>> {
+    apply_versions(
+      records,
+      self
+        .changes
+        .iter()
+        .filter_map(|c| c.after.as_ref())
+        .collect(),
+      self
+        .changes
+        .iter()
+        .filter_map(|c| c.before.as_ref())
+        .collect(),
+    )
+  }
+
+  pub fn redo(&self, records: &[Record]) -> OResult<Vec<Record>> {
+    apply_versions(
+      records,
+      self
+        .changes
+        .iter()
+        .filter_map(|c| c.before.as_ref())
+        .collect(),
+      self
+        .changes
+        .iter()
+        .filter_map(|c| c.after.as_ref())
+        .collect(),
+    )
+  }
+}
+
+impl Journal {
+  // Keeps the versions of the records in the same format as o_data, so
+  // undo restores the migrated dates
+  pub fn migrate_record_dates(&mut self) {
+    for change in self
+      .entries
+      .iter_mut()
+      .flat_map(|entry| entry.changes.iter_mut())
+    {
+      for version in change.before.iter_mut().chain(change.after.iter_mut()) {
+        migrate_record_dates(std::slice::from_mut(&mut version.record));
+      }
+    }
+  }
+
+  // Drops the undone entries and keeps at most `saved_actions` entries
+  pub fn push(&mut self, entry: JournalEntry, saved_actions: usize) {
+    self.entries.truncate(self.position);
+    self.entries.push(entry);
+
+    let excess = self.entries.len().saturating_sub(saved_actions);
+
+    self.entries.drain(0..excess);
+    self.position = self.entries.len();
+  }
+
+  pub fn get_undone_entries(&self) -> &[JournalEntry] {
+    &self.entries[self.position.min(self.entries.len())..]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::{RemoveMode, Store};
+
+  fn get_json(records: &[Record]) -> serde_json::Value {
+    serde_json::to_value(records).unwrap()
+  }
+
+  // Journals every step between the states, then undoes all of them one by
+  // one and redoes them again, checking each state on the way
+  fn check_undo_redo(states: &[Vec<Record>]) {
+    let entries: Vec<JournalEntry> = states
+      .windows(2)
+      .map(|pair| JournalEntry::new("test", diff_records(&pair[0], &pair[1])))
+      .collect();
+    let mut records = states[states.len() - 1].clone();
+
+    for (idx, entry) in entries.iter().enumerate().rev() {
+      records = entry.undo(&records).unwrap();
+      assert_eq!(get_json(&records), get_json(&states[idx]), "undo {}", idx);
+    }
+
+    for (idx, entry) in entries.iter().enumerate() {
+      records = entry.redo(&records).unwrap();
+      assert_eq!(
+        get_json(&records),
+        get_json(&states[idx + 1]),
+        "redo {}",
+        idx
+      );
+    }
+  }
+
+  fn get_states() -> Vec<Vec<Record>> {
+    let mut store = Store::from_records(vec![]);
+    let mut states: Vec<Vec<Record>> = vec![store.records().to_vec()];
+
+    for (what, location) in &[
+      ("shelf", "garage"),
+      ("drill", "shelf"),
+      ("saw", "shelf"),
+      ("box", "garage"),
+      ("tape", "box"),
+    ] {
+      store.insert(what, location, None).unwrap();
+      states.push(store.records().to_vec());
+    }
+
+    let drill_id = store.get_path("drill").unwrap().last().unwrap().id;
+
+    store.set_notes(drill_id, Some("cordless")).unwrap();
+    states.push(store.records().to_vec());
+
+    let saw_id = store.get_path("saw").unwrap().last().unwrap().id;
+
+    store.remove(saw_id, RemoveMode::Single).unwrap();
+    states.push(store.records().to_vec());
+
+    // the ids after the removed one are renumbered
+    store.optimize_ids();
+    states.push(store.records().to_vec());
+
+    let box_id = store.get_path("box").unwrap().last().unwrap().id;
+
+    store.move_item(box_id, "drill").unwrap();
+    states.push(store.records().to_vec());
+
+    states
+  }
+
+  #[test]
+  fn test_undo_redo_steps() {
+    let states = get_states();
+
+    assert_ne!(
+      get_json(&states[states.len() - 3]),
+      get_json(&states[states.len() - 2]),
+      "optimize_ids renumbers"
+    );
+    check_undo_redo(&states);
+  }
+
+  #[test]
+  fn test_undo_redo_reordered() {
+    let mut states = get_states();
+    let mut reordered = states[states.len() - 1].clone();
+
+    reordered.reverse();
+    reordered[0].notes = "changed while reordered".to_string();
+    states.push(reordered);
+
+    let mut rotated = states[states.len() - 1].clone();
+
+    rotated.rotate_left(1);
+    states.push(rotated);
+
+    check_undo_redo(&states);
+  }
+
+  #[test]
+  fn test_undo_redo_repeated_ids() {
+    let mut states = get_states();
+    let mut repeated = states[states.len() - 1].clone();
+    let mut copy = repeated[0].clone();
+
+    copy.what = "copy".to_string();
+    repeated.push(copy);
+    states.push(repeated);
+
+    let mut fixed = states[states.len() - 1].clone();
+
+    fixed.remove(0);
+    states.push(fixed);
+
+    check_undo_redo(&states);
+  }
+
+  #[test]
+  fn test_undo_mismatch() {
+    let states = get_states();
+    let entry = JournalEntry::new("test", diff_records(&states[1], &states[2]));
+
+    assert!(matches!(
+      entry.undo(&states[1]),
+      Err(OError::CorruptData(_))
+    ));
+  }
+}
@@ -0,0 +1,21 @@
+#[macro_use]
+extern crate serde_derive;
+
+pub mod attributes;
+pub mod check;
+pub mod data;
+pub mod diff;
+mod encryption;
+pub mod errors;
+pub mod exchange;
+mod fuzzy;
+pub mod journal;
+pub mod lock;
+mod migrations;
+pub mod query;
+pub mod storage;
+pub mod store;
+
+pub use crate::data::{Context, Record, TreeNode};
+pub use crate::errors::{OError, OResult};
+pub use crate::store::Store;
@@ -0,0 +1,64 @@
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use fs2::FileExt;
+
+use crate::errors::{OError, OResult};
+use crate::storage::get_project_dir;
+
+// Advisory lock over the project directory, released when dropped
+#[derive(Debug)]
+pub struct ProjectLock {
+  file: File,
+}
+
+impl Drop for ProjectLock {
+  fn drop(&mut self) {
+    self.file.unlock().ok();
+  }
+}
+
+fn get_lock_holder(file: &mut File) -> String {
+  let mut contents = String::new();
+
+  file.read_to_string(&mut contents).ok();
+
+  match contents.trim() {
+    "" => "an unknown process".to_string(),
+    pid => ["process ", pid].concat(),
+  }
+}
+
+pub fn lock_project(wait: bool) -> OResult<ProjectLock> {
+  lock_project_at(&get_project_dir()?, wait)
+}
+
+pub fn lock_project_at(dir_path: &str, wait: bool) -> OResult<ProjectLock> {
+  let lock_path = [dir_path, "/lock"].concat();
+  let mut file = OpenOptions::new()
+    .read(true)
+    .write(true)
+    .create(true)
+    .truncate(false)
+    .open(&lock_path)?;
+
+  if file.try_lock_exclusive().is_err() {
+    let holder = get_lock_holder(&mut file);
+
+    if !wait {
+      return Err(OError::Locked(holder));
+    }
+
+    eprintln!("Waiting for the lock held by {}", holder);
+
+    file.lock_exclusive()?;
+  }
+
+  file.set_len(0)?;
+  file.seek(SeekFrom::Start(0))?;
+  file.write_all(std::process::id().to_string().as_bytes())?;
+  file.sync_all()?;
+
+  Ok(ProjectLock { file })
+}
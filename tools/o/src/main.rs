@@ -1,84 +1,103 @@
-mod data;
-mod storage;
+mod output;
+mod printing;
 
-#[macro_use]
-extern crate serde_derive;
+use std::fs::write;
+use std::io::{self, BufRead, IsTerminal};
 
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::BufReader;
-
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
 use clap::{App, Arg, ArgMatches, SubCommand};
-use dirs::home_dir;
 
-use crate::data::{get_context, Context, Record};
-use crate::storage::{
-  handle_csv, init_project, optimize_records_ids, revert_data_to_backup, write_all_records,
+use o::attributes::{parse_assignments, AttributeValue};
+use o::data::{get_is_low_stock, parse_record_timestamp};
+use o::diff::{diff_data, DataDiff};
+use o::exchange::{format_records, read_records_file, ExchangeFormat, ExportOptions, GraphLabel};
+use o::journal::JournalEntry;
+use o::lock::lock_project;
+use o::query::Query;
+use o::storage::{
+  get_config, get_journal, init_project, read_csv_records, rekey_data, write_csv_records,
+  CsvOptions,
+};
+use o::store::{
+  get_id_str, sort_records, ConflictMode, ListSort, LocationCount, MergeFields, NodeType,
+  RemoveMode, SearchOptions, TreeOptions, TreeSort,
 };
+use o::{OError, OResult, Record, Store};
 
-fn get_now_date() -> String {
-  let now: DateTime<Local> = Local::now();
+use crate::output::OutputFormat;
+use crate::printing::{
+  print_ascii_tree, print_diff, print_issue, print_journal_entry, print_location_with_count,
+  print_merge_summary, print_path, print_record_line, print_record_path_line, print_stale_group,
+  print_stats, print_tree,
+};
 
-  now.format("%d/%m/%y").to_string()
+fn get_is_empty_text(txt: &str) -> bool {
+  txt.is_empty() || txt == "_"
 }
 
-fn get_data_records() -> Vec<Record> {
-  let mut file = File::open(".o/o_data");
-
-  if file.is_err() {
-    file = File::open([home_dir().unwrap().to_str().unwrap(), "/.o/o_data"].concat());
+fn get_contents<'a>(matches: &'a ArgMatches<'_>) -> OResult<Vec<&'a str>> {
+  match matches.values_of("CONTENT") {
+    Some(values) => Ok(values.collect()),
+    None => Err(OError::InvalidArgument("Missing content".to_string())),
   }
+}
 
-  let reader = BufReader::new(file.unwrap());
-  let records: Vec<Record> = serde_json::from_reader(reader).unwrap_or_else(|_| vec![]);
+fn parse_id(id_str: &str) -> OResult<usize> {
+  id_str.parse::<usize>().map_err(|_| {
+    OError::InvalidArgument(format!(
+      "You need to pass an id as first argument, found: {}",
+      id_str
+    ))
+  })
+}
 
-  records
+// Used to describe the write in the history
+fn get_command_line() -> String {
+  std::env::args().skip(1).collect::<Vec<String>>().join(" ")
 }
 
-fn get_is_empty_text(txt: &str) -> bool {
-  txt == "" || txt == "_"
+fn get_steps(matches: &ArgMatches<'_>, arg_name: &str) -> OResult<usize> {
+  match matches.value_of(arg_name) {
+    None => Ok(1),
+    Some(steps) => steps.parse::<usize>().map_err(|_| {
+      OError::InvalidArgument(format!("The steps must be a number, found: {}", steps))
+    }),
+  }
 }
 
-fn get_empty_notes_text() -> String {
-  "N/A".to_string()
+// Global flag, also present in the subcommands matches
+fn get_wait(matches: &ArgMatches<'_>) -> bool {
+  !matches.is_present("no-wait")
 }
 
-#[derive(Debug, Clone)]
-struct TreeNode {
-  children: HashSet<usize>,
-  parent: Option<usize>,
+// Global option, also present in the subcommands matches
+fn get_format(matches: &ArgMatches<'_>) -> OResult<OutputFormat> {
+  OutputFormat::parse(matches.value_of("format").unwrap_or("text"))
 }
 
-fn handle_search(matches: &ArgMatches<'_>) {
-  let records = get_data_records();
-  let contents = matches.values_of("CONTENT").unwrap().collect::<Vec<&str>>();
-  let skip_location = matches.is_present("skip-location");
-  let skip_what = matches.is_present("skip-what");
+fn get_date_format() -> OResult<String> {
+  Ok(get_config()?.date_format)
+}
 
-  for record in records {
-    let what_l = record.what.to_ascii_lowercase();
-    let location_l = record.location.to_ascii_lowercase();
-
-    for content in &contents {
-      let parsed = content.parse::<usize>();
-      if parsed.is_ok() {
-        let id = parsed.unwrap();
-
-        if !skip_what && record.what_id == id || !skip_location && record.location_id == id {
-          record.print_line();
-          break;
-        }
-      }
+// The write commands print what they changed when the output is structured
+// The changes are only printed with the structured formats, the text output
+// doesn't need the diff
+fn get_changes_diff(store: &Store, format: OutputFormat) -> Option<DataDiff> {
+  if format == OutputFormat::Text {
+    return None;
+  }
 
-      let content_l = content.to_ascii_lowercase();
-      if !skip_what && what_l.contains(&content_l)
-        || !skip_location && location_l.contains(&content_l)
-      {
-        record.print_line();
-        break;
-      }
+  Some(store.get_unsaved_diff())
+}
+
+fn print_changes(store: &Store, diff: Option<&DataDiff>, format: OutputFormat) -> OResult<bool> {
+  match diff {
+    Some(diff) if format != OutputFormat::Text => {
+      output::print_diff(diff, &store.context(), format)?;
+
+      Ok(true)
     }
+    _ => Ok(false),
   }
 }
 
@@ -87,7 +106,7 @@ fn get_full_contents(contents: &[&str]) -> Vec<String> {
 
   for content in contents.iter().skip(1) {
     if *content != "$" {
-      let last = full_contents.pop().unwrap();
+      let last = full_contents.pop().unwrap_or_default();
       let full_last = [last, content.to_string()].join(" ");
       full_contents.push(full_last.trim().to_string());
     } else {
@@ -98,341 +117,1055 @@ fn get_full_contents(contents: &[&str]) -> Vec<String> {
   full_contents
 }
 
-fn handle_insert(matches: &ArgMatches<'_>) {
-  let contents = matches.values_of("CONTENT").unwrap().collect::<Vec<&str>>();
-  let full_contents: Vec<String> = get_full_contents(&contents);
+// The `--attr key=value` options, with the types of the config schema
+fn get_attributes(matches: &ArgMatches<'_>) -> OResult<Vec<(String, Option<AttributeValue>)>> {
+  match matches.values_of("attr") {
+    Some(values) => parse_assignments(&values.collect::<Vec<&str>>(), &get_config()?.attributes),
+    None => Ok(vec![]),
+  }
+}
 
-  let mut records = get_data_records();
-  let notes = if full_contents.len() > 2 {
-    full_contents[2].to_string()
-  } else {
-    get_empty_notes_text()
+// `tab` can be used for the tab delimiter
+fn get_csv_options(matches: &ArgMatches<'_>) -> OResult<CsvOptions> {
+  let delimiter = match matches.value_of("delimiter").unwrap_or(",") {
+    "tab" | "\\t" => b'\t',
+    delimiter if delimiter.len() == 1 => delimiter.as_bytes()[0],
+    delimiter => {
+      return Err(OError::InvalidArgument(format!(
+        "The delimiter must be a single character or tab, found: {}",
+        delimiter
+      )));
+    }
   };
 
-  let context = get_context(&records);
+  Ok(CsvOptions {
+    columns: matches.value_of("columns").map(|columns| {
+      columns
+        .split(',')
+        .map(|column| column.to_string())
+        .collect()
+    }),
+    delimiter,
+    has_header: !matches.is_present("no-header"),
+    with_path: matches.is_present("path"),
+  })
+}
 
-  let mut what = full_contents[0].to_string();
-  let mut what_id = context.max_id + 1;
+// Replaces all the data, or merges it by name with `--merge`
+fn import_records(
+  matches: &ArgMatches<'_>,
+  records: Vec<Record>,
+  fields: &MergeFields,
+  format: OutputFormat,
+) -> OResult<()> {
+  let on_conflict = match matches.value_of("on-conflict").unwrap_or("overwrite") {
+    "skip" => ConflictMode::Skip,
+    "overwrite" => ConflictMode::Overwrite,
+    "fail" => ConflictMode::Fail,
+    on_conflict => {
+      return Err(OError::InvalidArgument(format!(
+        "Unknown passed conflict mode: {}",
+        on_conflict
+      )));
+    }
+  };
+  let mut store = Store::open_locked(get_wait(matches))?;
+
+  if !matches.is_present("merge") {
+    store.set_records(records);
+
+    let diff = get_changes_diff(&store, format);
+
+    store.save(&get_command_line())?;
+    print_changes(&store, diff.as_ref(), format)?;
+
+    return Ok(());
+  }
 
-  if what.parse::<usize>().is_ok() {
-    what_id = what.parse::<usize>().unwrap();
-    what = context.id_to_str_map[&what_id].clone();
-  } else if context.str_to_id_map.get(&what).is_some() {
-    what_id = context.str_to_id_map[&what];
+  let summary = store.merge(&records, fields, on_conflict)?;
+
+  if format == OutputFormat::Text {
+    print_merge_summary(&summary);
   }
 
-  let mut location = full_contents[1].to_string();
-  let mut location_id = context.max_id + 2;
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+  print_changes(&store, diff.as_ref(), format)?;
+
+  Ok(())
+}
+
+fn handle_csv(matches: &ArgMatches<'_>) -> OResult<()> {
+  let options = get_csv_options(matches)?;
+
+  if let Some(file_path) = matches.value_of("import") {
+    let format = get_format(matches)?;
+    let (records, fields) = read_csv_records(file_path, &get_config()?.attributes, &options)?;
 
-  if location.parse::<usize>().is_ok() {
-    location_id = location.parse::<usize>().unwrap();
-    location = context.id_to_str_map[&location_id].clone();
-  } else if context.str_to_id_map.get(&location).is_some() {
-    location_id = context.str_to_id_map[&location];
+    import_records(matches, records, &fields, format)?;
+  } else if let Some(file_path) = matches.value_of("export") {
+    write_csv_records(file_path, Store::open()?.records(), &options)?;
   }
 
-  for record in &records {
-    if record.what == what {
-      println!("Duplicated what: {}", what);
-      std::process::exit(1);
+  Ok(())
+}
+
+// In `export` and `import` the global option is the format of the file
+fn get_exchange_format(matches: &ArgMatches<'_>) -> OResult<ExchangeFormat> {
+  match matches.value_of("format") {
+    None => Err(OError::InvalidArgument(
+      "Pass the format of the file with --format, like --format yaml".to_string(),
+    )),
+    Some(format) => ExchangeFormat::parse(format),
+  }
+}
+
+// The labels are separated by commas, like `notes,count`
+fn get_export_options(matches: &ArgMatches<'_>) -> OResult<ExportOptions> {
+  Ok(ExportOptions {
+    root: match matches.value_of("root") {
+      None => None,
+      Some(root) => Some(root.parse::<usize>().map_err(|_| {
+        OError::InvalidArgument(format!("The root must be an id, found: {}", root))
+      })?),
+    },
+    labels: match matches.value_of("label") {
+      None => vec![],
+      Some(labels) => labels
+        .split(',')
+        .map(|label| GraphLabel::parse(label.trim()))
+        .collect::<OResult<Vec<GraphLabel>>>()?,
+    },
+  })
+}
+
+// Without a file the records are printed
+fn handle_export(matches: &ArgMatches<'_>) -> OResult<()> {
+  let contents = format_records(
+    Store::open()?.records(),
+    get_exchange_format(matches)?,
+    &get_export_options(matches)?,
+  )?;
+
+  match matches.value_of("FILE") {
+    Some(file_path) => write(file_path, contents)?,
+    None => print!("{}", contents),
+  }
+
+  Ok(())
+}
+
+fn handle_import(matches: &ArgMatches<'_>) -> OResult<()> {
+  let records = read_records_file(
+    matches.value_of("FILE").unwrap_or_default(),
+    get_exchange_format(matches)?,
+    &get_config()?.attributes,
+  )?;
+
+  import_records(
+    matches,
+    records,
+    &MergeFields::default(),
+    OutputFormat::Text,
+  )
+}
+
+fn get_limit(matches: &ArgMatches<'_>) -> OResult<Option<usize>> {
+  match matches.value_of("limit") {
+    None => Ok(None),
+    Some(limit) => limit.parse::<usize>().map(Some).map_err(|_| {
+      OError::InvalidArgument(format!("The limit must be a number, found: {}", limit))
+    }),
+  }
+}
+
+fn parse_quantity(quantity: &str) -> OResult<usize> {
+  quantity.parse::<usize>().map_err(|_| {
+    OError::InvalidArgument(format!(
+      "The quantity must be a positive number, found: {}",
+      quantity
+    ))
+  })
+}
+
+// None when the option is missing, and `-` clears the value
+fn get_quantity_option(matches: &ArgMatches<'_>, name: &str) -> OResult<Option<Option<usize>>> {
+  match matches.value_of(name) {
+    None => Ok(None),
+    Some("-") => Ok(Some(None)),
+    Some(quantity) => parse_quantity(quantity).map(|quantity| Some(Some(quantity))),
+  }
+}
+
+// Dates like 2024-01-31 start at the local midnight, and `--until` includes
+// the whole day
+fn get_time_bound(matches: &ArgMatches<'_>, name: &str) -> OResult<Option<DateTime<Local>>> {
+  let value = match matches.value_of(name) {
+    None => return Ok(None),
+    Some(value) => value,
+  };
+
+  if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+    return Ok(Some(timestamp.with_timezone(&Local)));
+  }
+
+  let (hour, min, sec) = if name == "until" {
+    (23, 59, 59)
+  } else {
+    (0, 0, 0)
+  };
+
+  NaiveDate::parse_from_str(value, "%Y-%m-%d")
+    .ok()
+    .and_then(|date| date.and_hms_opt(hour, min, sec))
+    .and_then(|date| Local.from_local_datetime(&date).earliest())
+    .map(Some)
+    .ok_or_else(|| {
+      OError::InvalidArgument(format!(
+        "The {} date must be like 2024-01-31 or an RFC3339 timestamp, found: {}",
+        name, value
+      ))
+    })
+}
+
+fn print_records(store: &Store, records: &[&Record], matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let context = store.context();
+
+  if format != OutputFormat::Text {
+    return output::print_records(records, &context, format);
+  }
+
+  for record in records {
+    if matches.is_present("path") {
+      print_record_path_line(record, &context, &date_format);
+    } else {
+      print_record_line(record, &date_format);
     }
   }
 
-  let created = get_now_date();
-  let updated = created.clone();
-  let new_record: Record = Record {
-    what,
-    what_id,
-    location,
-    location_id,
-    notes,
-    updated,
-    created,
+  Ok(())
+}
+
+fn get_query(matches: &ArgMatches<'_>) -> OResult<Option<Query>> {
+  match matches.value_of("query") {
+    None => Ok(None),
+    Some(query) => Query::parse(query).map(Some),
+  }
+}
+
+fn get_is_query_match(query: &Option<Query>, record: &Record) -> bool {
+  query.as_ref().is_none_or(|query| query.matches(record))
+}
+
+fn handle_search(matches: &ArgMatches<'_>) -> OResult<()> {
+  let store = Store::open()?;
+  let query = get_query(matches)?;
+  // with a query the contents are optional
+  let contents = match (get_contents(matches), &query) {
+    (Ok(contents), _) => contents,
+    (Err(_), Some(_)) => vec![],
+    (Err(err), None) => return Err(err),
+  };
+  let options = SearchOptions {
+    skip_what: matches.is_present("skip-what"),
+    skip_location: matches.is_present("skip-location"),
   };
 
-  records.push(new_record.clone());
+  let limit = get_limit(matches)?;
 
-  write_all_records(&records);
+  if matches.is_present("fuzzy") && !contents.is_empty() {
+    let scored_records = store.fuzzy_search(&contents, &options, usize::MAX);
+    let records: Vec<&Record> = scored_records
+      .iter()
+      .map(|scored_record| &scored_record.record)
+      .filter(|record| get_is_query_match(&query, record))
+      .take(limit.unwrap_or(10))
+      .collect();
+
+    return print_records(&store, &records, matches);
+  }
+
+  let records = if contents.is_empty() {
+    store.list(NodeType::All)
+  } else {
+    store.search(&contents, &options)
+  };
+  let records: Vec<&Record> = records
+    .into_iter()
+    .filter(|record| get_is_query_match(&query, record))
+    .take(limit.unwrap_or(usize::MAX))
+    .collect();
+
+  print_records(&store, &records, matches)
+}
+
+fn handle_insert(matches: &ArgMatches<'_>) -> OResult<()> {
+  let contents = get_contents(matches)?;
+  let full_contents: Vec<String> = get_full_contents(&contents);
+
+  if full_contents.len() < 2 {
+    return Err(OError::InvalidArgument(
+      "Insert needs a what and a location separated by $".to_string(),
+    ));
+  }
+
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let attributes = get_attributes(matches)?;
+  let quantity = get_quantity_option(matches, "qty")?;
+  let min_quantity = get_quantity_option(matches, "min")?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let notes = full_contents.get(2).map(|notes| notes.as_str());
+  let mut new_record = store.insert(&full_contents[0], &full_contents[1], notes)?;
+
+  if !attributes.is_empty() {
+    new_record = store.set_attributes(new_record.what_id, &attributes)?;
+  }
+
+  if quantity.is_some() || min_quantity.is_some() {
+    new_record = store.set_quantity(
+      new_record.what_id,
+      quantity.flatten(),
+      min_quantity.flatten(),
+    )?;
+  }
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
 
   println!("Inserted one record:");
 
-  new_record.print_line();
+  print_record_line(&new_record, &date_format);
+
+  Ok(())
 }
 
-fn handle_edit(matches: &ArgMatches<'_>) {
-  let contents = matches.values_of("CONTENT").unwrap().collect::<Vec<&str>>();
-  let id_str = contents[0];
+fn handle_edit(matches: &ArgMatches<'_>) -> OResult<()> {
+  let contents = get_contents(matches)?;
+  let what_id = parse_id(contents[0])?;
   let rest_contents: Vec<&str> = contents.iter().skip(1).cloned().collect();
-  let full_contents: Vec<String> = get_full_contents(&rest_contents);
+  let attributes = get_attributes(matches)?;
+  let quantity = get_quantity_option(matches, "qty")?;
+  let min_quantity = get_quantity_option(matches, "min")?;
+  let has_options = !attributes.is_empty() || quantity.is_some() || min_quantity.is_some();
+
+  if rest_contents.is_empty() && !has_options {
+    return Err(OError::InvalidArgument(
+      "Edit needs the new values, --attr, --qty or --min after the id".to_string(),
+    ));
+  }
+
+  let full_contents: Vec<String> = if rest_contents.is_empty() {
+    vec![]
+  } else {
+    get_full_contents(&rest_contents)
+  };
 
   // if first content is '' it would not have any chars
-  let first_content_chars = rest_contents[0].chars().take(1).collect::<Vec<char>>();
+  let first_content_chars = rest_contents
+    .first()
+    .map(|content| content.chars().take(1).collect::<Vec<char>>())
+    .unwrap_or_default();
   if !first_content_chars.is_empty() && first_content_chars[0] == '$' {
-    println!("Unexpected $ char as first item in edit");
-    std::process::exit(1);
-  }
-
-  let mut records = get_data_records();
-  let context = get_context(&records);
-  let what_id = id_str
-    .parse::<usize>()
-    .expect("You need to pass an id as first argument");
-
-  if context.id_to_str_map.get(&what_id).is_none()
-    || context.id_to_record_idx_map.get(&what_id).is_none()
-  {
-    if context.hierarchy.get(&what_id).is_none() || full_contents.len() != 1 {
-      println!("Unexisting id {}", what_id);
-      std::process::exit(1);
-    }
+    return Err(OError::InvalidArgument(
+      "Unexpected $ char as first item in edit".to_string(),
+    ));
+  }
 
-    // the edit is a rename of a location in 1..n records
-    let old_location_id = what_id;
+  let format = get_format(matches)?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let context = store.context();
 
-    let mut new_location = full_contents[0].clone();
-    let new_location_id: usize;
+  if !context.id_to_record_idx_map.contains_key(&what_id) {
+    // the edit is a rename of a location in 1..n records
+    if full_contents.len() != 1 || has_options {
+      return Err(OError::UnknownId(what_id));
+    }
 
-    if let Ok(val) = new_location.parse::<usize>() {
-      new_location_id = val;
-      new_location = context.id_to_str_map[&new_location_id].clone();
-    } else {
-      let maybe_location_id = context.str_to_id_map.get(&new_location);
-      new_location_id = if maybe_location_id.is_some() {
-        *maybe_location_id.unwrap()
-      } else {
-        context.max_id + 1
-      };
+    store.rename(what_id, &full_contents[0])?;
+  } else {
+    if !full_contents.is_empty() && !get_is_empty_text(&full_contents[0]) {
+      store.rename(what_id, &full_contents[0])?;
     }
 
-    for record in records.iter_mut() {
-      if record.location_id == old_location_id {
-        record.location_id = new_location_id;
-        record.location = new_location.clone();
-      }
+    if full_contents.len() > 1 && !get_is_empty_text(&full_contents[1]) {
+      store.move_item(what_id, &full_contents[1])?;
     }
-  } else {
-    let record_idx = context.id_to_record_idx_map[&what_id];
-    let mut new_what = full_contents[0].clone();
-    let mut new_what_id = what_id;
-
-    if !get_is_empty_text(&new_what) && new_what.as_str() != records[record_idx].what {
-      if let Ok(val) = new_what.parse::<usize>() {
-        new_what_id = val;
-        new_what = context.id_to_str_map[&new_what_id].clone();
-      }
 
-      if context.str_to_id_map.get(&new_what).is_some() {
-        panic!("Existing new what: {}", new_what);
-      }
+    if full_contents.len() > 2 && !get_is_empty_text(&full_contents[2]) {
+      let notes = full_contents[2].as_str();
 
-      for (idx, _) in records.clone().iter().enumerate() {
-        if records[idx].what_id == what_id {
-          records[idx].what = new_what.clone();
-          records[idx].what_id = new_what_id;
-        } else if records[idx].location_id == what_id {
-          records[idx].location = new_what.clone();
-        }
-      }
+      store.set_notes(what_id, if notes == "-" { None } else { Some(notes) })?;
     }
 
-    if full_contents.len() > 1 {
-      let mut new_location = full_contents[1].clone();
-
-      if !get_is_empty_text(&new_location) && new_location.as_str() != records[record_idx].location
-      {
-        let new_location_id;
-
-        if let Ok(val) = new_location.parse::<usize>() {
-          new_location_id = val;
-          new_location = context.id_to_str_map[&new_location_id].clone();
-        } else {
-          let maybe_location_id = context.str_to_id_map.get(&new_location);
-          new_location_id = if maybe_location_id.is_some() {
-            *maybe_location_id.unwrap()
-          } else {
-            context.max_id + 1
-          };
-        }
-
-        records[record_idx].location = new_location;
-        records[record_idx].location_id = new_location_id;
-      }
+    if !attributes.is_empty() {
+      store.set_attributes(what_id, &attributes)?;
     }
 
-    if full_contents.len() > 2 {
-      let mut new_notes = full_contents[2].clone();
-
-      if !get_is_empty_text(&new_notes) {
-        if new_notes.as_str() == "-" {
-          new_notes = get_empty_notes_text();
-        }
+    if quantity.is_some() || min_quantity.is_some() {
+      let record = store.records()[context.id_to_record_idx_map[&what_id]].clone();
 
-        records[record_idx].notes = new_notes;
-      }
+      store.set_quantity(
+        what_id,
+        quantity.unwrap_or(record.quantity),
+        min_quantity.unwrap_or(record.min_quantity),
+      )?;
     }
+  }
+
+  let diff = get_changes_diff(&store, format);
 
-    records[record_idx].updated = get_now_date();
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
   }
 
   println!("Record(s) updated correctly");
 
-  write_all_records(&records)
+  Ok(())
 }
 
-fn handle_remove(matches: &ArgMatches<'_>) {
-  let contents = matches.values_of("CONTENT").unwrap().collect::<Vec<&str>>();
-  let id_str = contents[0];
-  let what_id = id_str
-    .parse::<usize>()
-    .expect("You need to pass an id as first argument");
+fn handle_move(matches: &ArgMatches<'_>) -> OResult<()> {
+  let what_id = parse_id(matches.value_of("ID").unwrap_or(""))?;
+  let new_location = get_full_contents(&get_contents(matches)?).join(" ");
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let moved_item = store.move_item(what_id, &new_location)?;
+  let diff = get_changes_diff(&store, format);
 
-  let mut records = get_data_records();
-  let context = get_context(&records);
-  let record_idx = *context
-    .id_to_record_idx_map
-    .get(&what_id)
-    .expect("Unexisting id");
+  store.save(&get_command_line())?;
 
-  records.remove(record_idx);
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
 
-  println!("Record removed correctly");
+  println!(
+    "Moved to {} with {} descendant(s):",
+    store
+      .context()
+      .get_path_names(moved_item.record.location_id)
+      .join(" > "),
+    moved_item.descendants
+  );
 
-  write_all_records(&records)
-}
+  print_record_line(&moved_item.record, &date_format);
 
-fn handle_stats() {
-  let records = get_data_records();
-  let context = get_context(&records);
-  let mut root_nodes_num = 0;
+  Ok(())
+}
 
-  for id in context.hierarchy.keys() {
-    if context.hierarchy[id].parent.is_none() {
-      root_nodes_num += 1;
+fn handle_tag(matches: &ArgMatches<'_>) -> OResult<()> {
+  let (matches, is_add) = match matches.subcommand() {
+    ("add", Some(matches)) => (matches, true),
+    ("rm", Some(matches)) => (matches, false),
+    _ => {
+      return Err(OError::InvalidArgument(
+        "Expected `o tag add <id> <tag>...` or `o tag rm <id> <tag>...`".to_string(),
+      ))
     }
+  };
+  let what_id = parse_id(matches.value_of("ID").unwrap_or(""))?;
+  let tags: Vec<&str> = matches
+    .values_of("TAG")
+    .map_or(vec![], |tags| tags.collect());
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let record = if is_add {
+    store.add_tags(what_id, &tags)?
+  } else {
+    store.remove_tags(what_id, &tags)?
+  };
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
   }
 
-  println!("Stats:");
-  println!("- Count: {}", records.len());
-  println!("- Root nodes: {}", root_nodes_num);
+  println!("Tags updated:");
+
+  print_record_line(&record, &date_format);
+
+  Ok(())
 }
 
-fn handle_optimize_data() {
-  let mut records = get_data_records();
+fn handle_stock(matches: &ArgMatches<'_>, is_add: bool) -> OResult<()> {
+  let what_id = parse_id(matches.value_of("ID").unwrap_or(""))?;
+  let amount = parse_quantity(matches.value_of("N").unwrap_or(""))?;
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let record = if is_add {
+    store.add_quantity(what_id, amount)?
+  } else {
+    store.take_quantity(what_id, amount)?
+  };
+  let diff = get_changes_diff(&store, format);
 
-  optimize_records_ids(&mut records);
+  store.save(&get_command_line())?;
 
-  println!("Data was optimized successfully.");
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
+
+  println!("Quantity updated:");
+
+  print_record_line(&record, &date_format);
+
+  Ok(())
+}
 
-  write_all_records(&records)
+// Ages like 180d, 8w, 6m or 1y, the months have 30 days and the years 365
+fn parse_age(age: &str) -> OResult<Duration> {
+  let get_error = || {
+    OError::InvalidArgument(format!(
+      "The age must be a number with d, w, m or y, like 180d, found: {}",
+      age
+    ))
+  };
+  let split_idx = age.char_indices().last().map_or(0, |(idx, _)| idx);
+  let (number, unit) = age.split_at(split_idx);
+  let number = number.parse::<i64>().map_err(|_| get_error())?;
+  let days = match unit {
+    "d" => 1,
+    "w" => 7,
+    "m" => 30,
+    "y" => 365,
+    _ => return Err(get_error()),
+  };
+
+  Ok(Duration::days(number * days))
 }
 
-fn handle_list(matches: &ArgMatches<'_>) {
-  let records = get_data_records();
-  let node_type = matches.value_of("node-type").unwrap_or("all");
+fn handle_stale(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let older_than = parse_age(matches.value_of("older-than").unwrap_or("180d"))?;
+  let under = match matches.value_of("under") {
+    None => None,
+    Some(id) => Some(parse_id(id)?),
+  };
+  let store = Store::open()?;
+  let groups = store.stale(older_than, under)?;
+  let context = store.context();
 
-  match node_type {
-    "all" | "root" | "leaf" => {}
-    _ => {
-      panic!("Unknown passed node type");
-    }
+  if format != OutputFormat::Text {
+    let records: Vec<&Record> = groups.iter().flat_map(|group| &group.records).collect();
+
+    return output::print_records(&records, &context, format);
+  }
+
+  if groups.is_empty() {
+    println!("No stale items");
   }
 
-  if node_type == "all" {
-    for record in records {
-      record.print_line();
+  for group in &groups {
+    print_stale_group(group, &context, &date_format);
+  }
+
+  Ok(())
+}
+
+fn handle_touch(matches: &ArgMatches<'_>) -> OResult<()> {
+  let ids: Vec<usize> = matches
+    .values_of("ID")
+    .map_or(vec![], |ids| ids.collect())
+    .into_iter()
+    .map(parse_id)
+    .collect::<OResult<Vec<usize>>>()?;
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let touched = store.touch(&ids)?;
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
+
+  println!("Marked {} record(s) as verified:", touched.len());
+
+  for record in &touched {
+    print_record_line(record, &date_format);
+  }
+
+  Ok(())
+}
+
+fn handle_remove(matches: &ArgMatches<'_>) -> OResult<()> {
+  let contents = get_contents(matches)?;
+  let what_id = parse_id(contents[0])?;
+  let mode = if matches.is_present("recursive") {
+    RemoveMode::Recursive
+  } else if matches.is_present("reparent") {
+    RemoveMode::Reparent
+  } else {
+    RemoveMode::Single
+  };
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let children = store.get_children(what_id);
+
+  if mode == RemoveMode::Single && !children.is_empty() && format == OutputFormat::Text {
+    println!("Items inside {}:", get_id_str(&store.context(), what_id)?);
+
+    for record in children {
+      print_record_line(record, &date_format);
     }
+  }
+
+  let removed_items = store.remove(what_id, mode)?;
+  let diff = get_changes_diff(&store, format);
 
-    return;
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
   }
 
-  let context = get_context(&records);
+  println!("Removed {} record(s):", removed_items.removed.len());
 
-  if node_type == "root" {
-    let mut printed_ids: HashSet<usize> = HashSet::new();
+  for record in &removed_items.removed {
+    print_record_line(record, &date_format);
+  }
 
-    for record in records {
-      if context.hierarchy[&record.location_id].parent.is_none()
-        && !printed_ids.contains(&record.location_id)
-      {
-        record.print_location_with_count(&context);
-        printed_ids.insert(record.location_id);
-      }
+  if !removed_items.reparented.is_empty() {
+    println!("Moved up {} record(s):", removed_items.reparented.len());
+
+    for record in &removed_items.reparented {
+      print_record_line(record, &date_format);
     }
-  } else if node_type == "leaf" {
-    for record in records {
-      if context.hierarchy[&record.what_id].children.is_empty() {
-        record.print_line();
+  }
+
+  Ok(())
+}
+
+fn handle_check(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+
+  if !matches.is_present("fix") {
+    let issues = Store::open()?.check();
+
+    if format != OutputFormat::Text {
+      output::print_issues(&issues, format)?;
+    } else if issues.is_empty() {
+      println!("No issues found");
+    } else {
+      for issue in &issues {
+        print_issue(issue, &date_format);
       }
     }
+
+    if issues.is_empty() {
+      return Ok(());
+    }
+
+    return Err(OError::CorruptData(format!(
+      "{} issue(s) found, {} can be repaired with `o check --fix`",
+      issues.len(),
+      issues.iter().filter(|issue| issue.fixable).count()
+    )));
+  }
+
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let fixable_count = store.check().iter().filter(|issue| issue.fixable).count();
+  let remaining_issues = store.fix();
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+
+  if !print_changes(&store, diff.as_ref(), format)? {
+    println!("Repaired {} issue(s)", fixable_count);
+
+    for issue in &remaining_issues {
+      print_issue(issue, &date_format);
+    }
   }
+
+  if remaining_issues.is_empty() {
+    return Ok(());
+  }
+
+  Err(OError::CorruptData(format!(
+    "{} issue(s) need a manual repair",
+    remaining_issues.len()
+  )))
 }
 
-fn handle_tree() {
-  fn print_recursive(
-    record_id: usize,
-    context: &Context,
-    records: &[Record],
-    depth: usize,
-  ) -> usize {
-    let str = context.id_to_str_map[&record_id].clone();
-    let mut last_depth = depth;
-    let tree_node = context.hierarchy.get(&record_id);
-    let has_children = match tree_node {
-      None => false,
-      Some(v) => !v.children.is_empty(),
-    };
-    let prefix = if has_children { "+" } else { "-" };
-
-    println!(
-      "{}{} {} [{}]",
-      " ".repeat(depth * 6),
-      prefix,
-      str,
-      record_id
-    );
+fn handle_stats(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let stats = Store::open()?.stats();
 
-    if tree_node.is_some() {
-      for (idx, child_id) in tree_node.unwrap().children.iter().enumerate() {
-        if idx == 0 {
-          last_depth = depth + 1;
-        }
+  if format != OutputFormat::Text {
+    return output::print_stats(&stats, format);
+  }
 
-        if last_depth != depth + 1 {
-          println!();
-        }
+  print_stats(&stats);
 
-        last_depth = print_recursive(*child_id, &context, &records, depth + 1);
-      }
+  Ok(())
+}
+
+fn handle_optimize_data(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+
+  store.optimize_ids();
+
+  let diff = get_changes_diff(&store, format);
+
+  store.save(&get_command_line())?;
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
+
+  println!("Data was optimized successfully.");
+
+  Ok(())
+}
+
+fn handle_list(matches: &ArgMatches<'_>) -> OResult<()> {
+  let store = Store::open()?;
+  let query = get_query(matches)?;
+  let node_type = match matches.value_of("node-type").unwrap_or("all") {
+    "all" => NodeType::All,
+    "root" => NodeType::Root,
+    "leaf" => NodeType::Leaf,
+    node_type => {
+      return Err(OError::InvalidArgument(format!(
+        "Unknown passed node type: {}",
+        node_type
+      )));
+    }
+  };
+
+  if node_type == NodeType::Root {
+    let root_records = store.list(NodeType::Root);
+    let root_locations = store.root_locations();
+    // only the locations that contain a matching record are kept
+    let locations: Vec<&LocationCount> = root_locations
+      .iter()
+      .filter(|location| {
+        root_records
+          .iter()
+          .any(|record| record.location_id == location.id && get_is_query_match(&query, record))
+      })
+      .collect();
+    let format = get_format(matches)?;
+
+    if format != OutputFormat::Text {
+      return output::print_locations(&locations, format);
+    }
+
+    for location in locations {
+      print_location_with_count(location);
     }
 
-    last_depth
+    return Ok(());
   }
 
-  let records = get_data_records();
-  let context = get_context(&records);
+  let is_low_stock = matches.is_present("low-stock");
+  let since = get_time_bound(matches, "since")?;
+  let until = get_time_bound(matches, "until")?;
+  let sort = match matches.value_of("sort").unwrap_or("created") {
+    "created" => ListSort::Created,
+    "updated" => ListSort::Updated,
+    "name" => ListSort::Name,
+    "id" => ListSort::Id,
+    sort => {
+      return Err(OError::InvalidArgument(format!(
+        "Unknown passed sort: {}",
+        sort
+      )));
+    }
+  };
+  let tags: Vec<String> = matches
+    .values_of("tag")
+    .map_or(vec![], |tags| tags.map(|tag| tag.to_lowercase()).collect());
+  let mut records: Vec<&Record> = store
+    .list(node_type)
+    .into_iter()
+    .filter(|record| get_is_query_match(&query, record))
+    // every tag is required
+    .filter(|record| tags.iter().all(|tag| record.tags.contains(tag)))
+    .filter(|record| !is_low_stock || get_is_low_stock(record))
+    .filter(|record| {
+      let updated = parse_record_timestamp(&record.updated);
+
+      since.is_none_or(|since| updated.is_some_and(|updated| updated >= since))
+        && until.is_none_or(|until| updated.is_some_and(|updated| updated <= until))
+    })
+    .collect();
+
+  sort_records(&mut records, sort);
+
+  print_records(&store, &records, matches)
+}
+
+fn handle_path(matches: &ArgMatches<'_>) -> OResult<()> {
+  let contents = get_contents(matches)?;
+  let format = get_format(matches)?;
+  let path = Store::open()?.get_path(&contents.join(" "))?;
+
+  if format != OutputFormat::Text {
+    return output::print_path(&path, format);
+  }
 
-  println!("<top>");
+  print_path(&path);
 
-  let mut depth = 1;
-  for id in context.hierarchy.keys() {
-    if context.hierarchy[id].parent.is_none() {
-      if depth != 1 {
-        println!();
+  Ok(())
+}
+
+fn handle_tree(matches: &ArgMatches<'_>) -> OResult<()> {
+  let store = Store::open()?;
+  let format = get_format(matches)?;
+  let options = TreeOptions {
+    root: match matches.value_of("ID") {
+      None => None,
+      Some(id) => Some(parse_id(id)?),
+    },
+    depth: match matches.value_of("depth") {
+      None => None,
+      Some(depth) => Some(depth.parse::<usize>().map_err(|_| {
+        OError::InvalidArgument(format!("The depth must be a number, found: {}", depth))
+      })?),
+    },
+    sort: match matches.value_of("sort").unwrap_or("name") {
+      "name" => TreeSort::Name,
+      "id" => TreeSort::Id,
+      "updated" => TreeSort::Updated,
+      sort => {
+        return Err(OError::InvalidArgument(format!(
+          "Unknown passed sort: {}",
+          sort
+        )));
       }
+    },
+  };
+  let items = match get_query(matches)? {
+    None => store.tree(&options)?,
+    Some(query) => store.filtered_tree(&query, &options)?,
+  };
+
+  if format != OutputFormat::Text {
+    return output::print_tree(&items, format);
+  }
+
+  if matches.is_present("ascii") {
+    print_ascii_tree(&items);
+  } else {
+    print_tree(&items);
+  }
+
+  Ok(())
+}
+
+fn handle_undo(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let position = get_journal()?.position;
+  let records = (format != OutputFormat::Text).then(|| store.records().to_vec());
+  let entries = store.undo(get_steps(matches, "STEPS")?)?;
+  let diff = records.map(|records| diff_data(&records, store.records()));
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
+
+  println!("Undone:");
+
+  for (idx, entry) in entries.iter().enumerate() {
+    print_journal_entry(position - idx, entry, true, &date_format);
+  }
+
+  Ok(())
+}
+
+fn handle_redo(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let mut store = Store::open_locked(get_wait(matches))?;
+  let position = get_journal()?.position;
+  let records = (format != OutputFormat::Text).then(|| store.records().to_vec());
+  let entries = store.redo(get_steps(matches, "STEPS")?)?;
+  let diff = records.map(|records| diff_data(&records, store.records()));
+
+  if print_changes(&store, diff.as_ref(), format)? {
+    return Ok(());
+  }
+
+  println!("Redone:");
+
+  for (idx, entry) in entries.iter().enumerate() {
+    print_journal_entry(position + idx + 1, entry, false, &date_format);
+  }
 
-      depth = print_recursive(*id, &context, &records, 1);
+  Ok(())
+}
+
+fn handle_diff(matches: &ArgMatches<'_>) -> OResult<()> {
+  let date_format = get_date_format()?;
+  let store = Store::open()?;
+  let diff = store.diff_with_history(get_steps(matches, "backup")?)?;
+
+  if !print_changes(&store, Some(&diff), get_format(matches)?)? {
+    print_diff(&diff, &date_format);
+  }
+
+  Ok(())
+}
+
+fn handle_history(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let date_format = get_date_format()?;
+  let journal = get_journal()?;
+
+  if format != OutputFormat::Text {
+    let entries: Vec<(usize, &JournalEntry, bool)> = journal
+      .entries
+      .iter()
+      .enumerate()
+      .rev()
+      .map(|(idx, entry)| (idx + 1, entry, idx >= journal.position))
+      .collect();
+
+    return output::print_journal_entries(&entries, format);
+  }
+
+  for (idx, entry) in journal.entries.iter().enumerate().rev() {
+    print_journal_entry(idx + 1, entry, idx >= journal.position, &date_format);
+  }
+
+  Ok(())
+}
+
+// The key is read from stdin so it doesn't end up in the shell history or in
+// the process list. A terminal asks for it twice to catch typos.
+fn read_new_key() -> OResult<String> {
+  let stdin = io::stdin();
+  let is_terminal = stdin.is_terminal();
+  let read_key = |prompt: &str| -> OResult<String> {
+    let mut line = String::new();
+
+    if is_terminal {
+      eprint!("{}", prompt);
     }
+
+    if stdin.lock().read_line(&mut line)? == 0 {
+      return Err(OError::InvalidArgument(
+        "No key was read from stdin, nothing was changed".to_string(),
+      ));
+    }
+
+    Ok(line.trim_end_matches(['\n', '\r']).to_string())
+  };
+  let new_key = read_key("New encryption key (empty disables the encryption): ")?;
+
+  if is_terminal && read_key("Repeat the new key: ")? != new_key {
+    return Err(OError::InvalidArgument(
+      "The keys don't match, nothing was changed".to_string(),
+    ));
+  }
+
+  if new_key.chars().any(char::is_control) {
+    return Err(OError::InvalidArgument(
+      "The key can't contain control characters".to_string(),
+    ));
+  }
+
+  Ok(new_key)
+}
+
+fn handle_rekey(matches: &ArgMatches<'_>) -> OResult<()> {
+  let format = get_format(matches)?;
+  let new_key = read_new_key()?;
+  let _lock = lock_project(get_wait(matches))?;
+  let legacy_backup_paths = rekey_data(&new_key)?;
+
+  if legacy_backup_paths.is_empty() {
+    return output::print_message("Data and history were rekeyed", format);
   }
+
+  output::print_message(
+    &format!(
+      "Data, history and the {} old backups in .o/backups were rekeyed. The backups are no longer used since the history is kept in the journal, delete them when they are not needed",
+      legacy_backup_paths.len()
+    ),
+    format,
+  )
+}
+
+fn get_query_arg<'a, 'b>() -> Arg<'a, 'b> {
+  Arg::with_name("query")
+    .long("query")
+    .short("q")
+    .value_name("QUERY")
+    .help("Filter like 'what:/^usb/ notes:warranty location:garage updated:<2024-01-01', with AND, OR, NOT and parentheses")
 }
 
-fn handle_revert() {
-  revert_data_to_backup();
+fn get_attr_arg<'a, 'b>() -> Arg<'a, 'b> {
+  Arg::with_name("attr")
+    .long("attr")
+    .short("a")
+    .value_name("KEY=VALUE")
+    .multiple(true)
+    .number_of_values(1)
+    .help("Sets a custom attribute like price=20 or bought=2024-01-31, an empty value removes it")
 }
 
-fn parse_args() {
+fn get_quantity_args<'a, 'b>() -> [Arg<'a, 'b>; 2] {
+  [
+    Arg::with_name("qty")
+      .long("qty")
+      .value_name("N")
+      .help("Sets the quantity of the item, `-` stops tracking it"),
+    Arg::with_name("min")
+      .long("min")
+      .value_name("N")
+      .help("Sets the low-stock threshold of the item, `-` removes it"),
+  ]
+}
+
+fn parse_args() -> OResult<()> {
   let mut app = App::new("o")
     .version("1.0")
     .about("Organizing helpers")
+    .arg(
+      Arg::with_name("wait")
+        .long("wait")
+        .global(true)
+        .overrides_with("no-wait")
+        .help("Waits for other o processes writing the data (default)"),
+    )
+    .arg(
+      Arg::with_name("no-wait")
+        .long("no-wait")
+        .global(true)
+        .overrides_with("wait")
+        .help("Fails instead of waiting for other o processes writing the data"),
+    )
+    .arg(
+      Arg::with_name("format")
+        .long("format")
+        .global(true)
+        .value_name("FORMAT")
+        .help("Output format ['text' | 'json' | 'jsonl' | 'csv' | 'tsv'], 'text' by default. In export and import, format of the file ['jsonl' | 'yaml' | 'markdown' | 'dot' | 'mermaid']"),
+    )
     .subcommand(SubCommand::with_name("init").about("Inits a new project"))
     .subcommand(
       SubCommand::with_name("csv")
@@ -444,6 +1177,45 @@ fn parse_args() {
             .value_name("FILE")
             .help("imports CSV"),
         )
+        .arg(
+          Arg::with_name("merge")
+            .long("merge")
+            .short("m")
+            .requires("import")
+            .help("Merges the imported items with the existing ones by name instead of replacing all the data"),
+        )
+        .arg(
+          Arg::with_name("on-conflict")
+            .long("on-conflict")
+            .value_name("MODE")
+            .requires("merge")
+            .help("What to do with the existing items that differ ['skip' | 'overwrite' | 'fail'], 'overwrite' by default"),
+        )
+        .arg(
+          Arg::with_name("columns")
+            .long("columns")
+            .short("c")
+            .value_name("COLUMNS")
+            .help("Columns separated by commas, like what,location,path,price. Without a header they are also the columns of the import"),
+        )
+        .arg(
+          Arg::with_name("delimiter")
+            .long("delimiter")
+            .short("d")
+            .value_name("CHAR")
+            .help("Delimiter of the columns, a single character or tab, ',' by default"),
+        )
+        .arg(
+          Arg::with_name("no-header")
+            .long("no-header")
+            .help("The file has no header row"),
+        )
+        .arg(
+          Arg::with_name("path")
+            .long("path")
+            .short("p")
+            .help("Exports the full path of the location of each item as the path column"),
+        )
         .arg(
           Arg::with_name("export")
             .long("export")
@@ -452,6 +1224,46 @@ fn parse_args() {
             .help("exports CSV"),
         ),
     )
+    .subcommand(
+      SubCommand::with_name("export")
+        .about("Exports the items with --format jsonl|yaml|markdown, or a graph of the locations with --format dot|mermaid")
+        .arg(
+          Arg::with_name("FILE")
+            .help("File to write, the items are printed without it"),
+        )
+        .arg(
+          Arg::with_name("root")
+            .long("root")
+            .short("r")
+            .value_name("ID")
+            .help("Starts the graph at this node"),
+        )
+        .arg(
+          Arg::with_name("label")
+            .long("label")
+            .short("l")
+            .value_name("LABELS")
+            .help("Extra labels of the graph nodes separated by commas ['notes' | 'count']"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("import")
+        .about("Imports the items of an export with --format jsonl|yaml|markdown, keeping their ids")
+        .arg(Arg::with_name("FILE").required(true))
+        .arg(
+          Arg::with_name("merge")
+            .long("merge")
+            .short("m")
+            .help("Merges the imported items with the existing ones by name instead of replacing all the data"),
+        )
+        .arg(
+          Arg::with_name("on-conflict")
+            .long("on-conflict")
+            .value_name("MODE")
+            .requires("merge")
+            .help("What to do with the existing items that differ ['skip' | 'overwrite' | 'fail'], 'overwrite' by default"),
+        ),
+    )
     .subcommand(
       SubCommand::with_name("se")
         .about("Search")
@@ -467,68 +1279,309 @@ fn parse_args() {
             .short("l")
             .help("Skips location from search"),
         )
+        .arg(
+          Arg::with_name("path")
+            .long("path")
+            .short("p")
+            .help("Displays the full path of each item"),
+        )
+        .arg(
+          Arg::with_name("fuzzy")
+            .long("fuzzy")
+            .short("f")
+            .help("Tolerates typos, also searches the notes and sorts by best match"),
+        )
+        .arg(get_query_arg())
+        .arg(
+          Arg::with_name("limit")
+            .long("limit")
+            .value_name("N")
+            .help("Maximum number of results, 10 by default with --fuzzy"),
+        )
         .arg(Arg::with_name("CONTENT").multiple(true)),
     )
+    .subcommand(
+      SubCommand::with_name("path")
+        .about("Display the full path of an item or location")
+        .arg(
+          Arg::with_name("CONTENT")
+            .required(true)
+            .multiple(true)
+            .help("Id or name"),
+        ),
+    )
     .subcommand(
       SubCommand::with_name("in")
         .about("Insert")
-        .arg(Arg::with_name("CONTENT").multiple(true)),
+        .arg(Arg::with_name("CONTENT").multiple(true))
+        .arg(get_attr_arg())
+        .args(&get_quantity_args()),
     )
     .subcommand(
       SubCommand::with_name("ed")
         .about("Edit")
-        .arg(Arg::with_name("CONTENT").multiple(true)),
+        .arg(Arg::with_name("CONTENT").multiple(true))
+        .arg(get_attr_arg())
+        .args(&get_quantity_args()),
+    )
+    .subcommand(
+      SubCommand::with_name("mv")
+        .about("Move an item with its whole subtree")
+        .arg(Arg::with_name("ID").required(true))
+        .arg(
+          Arg::with_name("CONTENT")
+            .required(true)
+            .multiple(true)
+            .help("New location, as an id or a name"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("add")
+        .about("Add to the quantity of an item")
+        .arg(Arg::with_name("ID").required(true))
+        .arg(Arg::with_name("N").required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("take")
+        .about("Take from the quantity of an item")
+        .arg(Arg::with_name("ID").required(true))
+        .arg(Arg::with_name("N").required(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("stale")
+        .about("List the items not updated in a while, grouped by container and oldest first")
+        .arg(
+          Arg::with_name("older-than")
+            .long("older-than")
+            .short("o")
+            .value_name("AGE")
+            .help("Minimum age of the items, like 180d, 8w, 6m or 1y, 180d by default"),
+        )
+        .arg(
+          Arg::with_name("under")
+            .long("under")
+            .short("u")
+            .value_name("ID")
+            .help("Lists only the items inside this location"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("touch")
+        .about("Mark items as verified now, without changing anything else")
+        .arg(Arg::with_name("ID").required(true).multiple(true)),
+    )
+    .subcommand(
+      SubCommand::with_name("tag")
+        .about("Add or remove tags of an item")
+        .subcommand(
+          SubCommand::with_name("add")
+            .about("Add tags to an item")
+            .arg(Arg::with_name("ID").required(true))
+            .arg(Arg::with_name("TAG").required(true).multiple(true)),
+        )
+        .subcommand(
+          SubCommand::with_name("rm")
+            .about("Remove tags from an item")
+            .arg(Arg::with_name("ID").required(true))
+            .arg(Arg::with_name("TAG").required(true).multiple(true)),
+        ),
     )
     .subcommand(
       SubCommand::with_name("rm")
         .about("Remove")
+        .arg(
+          Arg::with_name("recursive")
+            .long("recursive")
+            .short("r")
+            .help("Removes the items inside it as well"),
+        )
+        .arg(
+          Arg::with_name("reparent")
+            .long("reparent")
+            .conflicts_with("recursive")
+            .help("Moves the items inside it to its location"),
+        )
         .arg(Arg::with_name("CONTENT").multiple(true)),
     )
     .subcommand(SubCommand::with_name("st").about("Stats"))
+    .subcommand(
+      SubCommand::with_name("check")
+        .about("Report the inconsistencies in the data")
+        .arg(
+          Arg::with_name("fix")
+            .long("fix")
+            .help("Applies the safe repairs, which can be undone"),
+        ),
+    )
     .subcommand(SubCommand::with_name("optimize-data").about("Optimize data"))
-    .subcommand(SubCommand::with_name("tree").about("Display in a tree fashion"))
     .subcommand(
-      SubCommand::with_name("rev").about("Revert previous write operation from the backup"),
+      SubCommand::with_name("tree")
+        .about("Display in a tree fashion")
+        .arg(Arg::with_name("ID").help("Displays only the subtree of this id"))
+        .arg(
+          Arg::with_name("depth")
+            .long("depth")
+            .short("d")
+            .value_name("N")
+            .help("Levels displayed below the top nodes"),
+        )
+        .arg(
+          Arg::with_name("sort")
+            .long("sort")
+            .short("s")
+            .value_name("VALUE")
+            .help("Order of the nodes ['name' | 'id' | 'updated'], 'name' by default"),
+        )
+        .arg(
+          Arg::with_name("ascii")
+            .long("ascii")
+            .help("Uses the previous ASCII style, without counts"),
+        )
+        .arg(get_query_arg()),
+    )
+    .subcommand(SubCommand::with_name("rev").about("Undo the previous write operation"))
+    .subcommand(
+      SubCommand::with_name("undo")
+        .about("Undo the latest write operations")
+        .arg(Arg::with_name("STEPS").help("Number of operations, 1 by default")),
     )
     .subcommand(
-      SubCommand::with_name("ls").about("List").arg(
-        Arg::with_name("node-type")
-          .long("node-type")
-          .short("n")
-          .value_name("VALUE")
-          .help("Node type ['root' | 'leaf' | 'all']"),
-      ),
+      SubCommand::with_name("redo")
+        .about("Redo the latest undone write operations")
+        .arg(Arg::with_name("STEPS").help("Number of operations, 1 by default")),
+    )
+    .subcommand(SubCommand::with_name("history").about("Lists the journaled write operations"))
+    .subcommand(
+      SubCommand::with_name("diff")
+        .about("Compares the data with how it was before the latest write operations")
+        .arg(
+          Arg::with_name("backup")
+            .long("backup")
+            .short("b")
+            .value_name("N")
+            .help("Number of write operations to go back, 1 by default"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("rekey")
+        .about("Re-encrypts the data, the history and the old backups with a new key read from stdin, an empty line disables the encryption"),
+    )
+    .subcommand(
+      SubCommand::with_name("ls")
+        .about("List")
+        .arg(
+          Arg::with_name("node-type")
+            .long("node-type")
+            .short("n")
+            .value_name("VALUE")
+            .help("Node type ['root' | 'leaf' | 'all']"),
+        )
+        .arg(
+          Arg::with_name("path")
+            .long("path")
+            .short("p")
+            .help("Displays the full path of each item"),
+        )
+        .arg(
+          Arg::with_name("tag")
+            .long("tag")
+            .short("t")
+            .value_name("TAG")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Lists only the items with this tag, can be repeated"),
+        )
+        .arg(
+          Arg::with_name("low-stock")
+            .long("low-stock")
+            .help("Lists only the items with a quantity at or below their minimum"),
+        )
+        .arg(
+          Arg::with_name("sort")
+            .long("sort")
+            .short("s")
+            .value_name("VALUE")
+            .help("Order of the items ['created' | 'updated' | 'name' | 'id'], 'created' by default, the latest updated go first"),
+        )
+        .arg(
+          Arg::with_name("since")
+            .long("since")
+            .value_name("DATE")
+            .help("Lists only the items updated from this date, like 2024-01-31 or an RFC3339 timestamp"),
+        )
+        .arg(
+          Arg::with_name("until")
+            .long("until")
+            .value_name("DATE")
+            .help("Lists only the items updated until this date, included"),
+        )
+        .arg(get_query_arg()),
     );
 
   let matches = app.clone().get_matches();
 
   if matches.subcommand_matches("init").is_some() {
-    init_project();
+    init_project()
   } else if let Some(matches) = matches.subcommand_matches("csv") {
-    handle_csv(matches);
+    handle_csv(matches)
+  } else if let Some(matches) = matches.subcommand_matches("export") {
+    handle_export(matches)
+  } else if let Some(matches) = matches.subcommand_matches("import") {
+    handle_import(matches)
   } else if let Some(matches) = matches.subcommand_matches("se") {
-    handle_search(matches);
+    handle_search(matches)
+  } else if let Some(matches) = matches.subcommand_matches("path") {
+    handle_path(matches)
   } else if let Some(matches) = matches.subcommand_matches("in") {
-    handle_insert(matches);
+    handle_insert(matches)
   } else if let Some(matches) = matches.subcommand_matches("ed") {
-    handle_edit(matches);
+    handle_edit(matches)
+  } else if let Some(matches) = matches.subcommand_matches("mv") {
+    handle_move(matches)
+  } else if let Some(matches) = matches.subcommand_matches("add") {
+    handle_stock(matches, true)
+  } else if let Some(matches) = matches.subcommand_matches("take") {
+    handle_stock(matches, false)
+  } else if let Some(matches) = matches.subcommand_matches("stale") {
+    handle_stale(matches)
+  } else if let Some(matches) = matches.subcommand_matches("touch") {
+    handle_touch(matches)
+  } else if let Some(matches) = matches.subcommand_matches("tag") {
+    handle_tag(matches)
   } else if let Some(matches) = matches.subcommand_matches("rm") {
-    handle_remove(matches);
-  } else if matches.subcommand_matches("st").is_some() {
-    handle_stats();
-  } else if matches.subcommand_matches("optimize-data").is_some() {
-    handle_optimize_data();
-  } else if matches.subcommand_matches("tree").is_some() {
-    handle_tree();
-  } else if matches.subcommand_matches("rev").is_some() {
-    handle_revert();
+    handle_remove(matches)
+  } else if let Some(matches) = matches.subcommand_matches("st") {
+    handle_stats(matches)
+  } else if let Some(matches) = matches.subcommand_matches("check") {
+    handle_check(matches)
+  } else if let Some(matches) = matches.subcommand_matches("optimize-data") {
+    handle_optimize_data(matches)
+  } else if let Some(matches) = matches.subcommand_matches("tree") {
+    handle_tree(matches)
+  } else if let Some(matches) = matches.subcommand_matches("rev") {
+    handle_undo(matches)
+  } else if let Some(matches) = matches.subcommand_matches("undo") {
+    handle_undo(matches)
+  } else if let Some(matches) = matches.subcommand_matches("redo") {
+    handle_redo(matches)
+  } else if let Some(matches) = matches.subcommand_matches("history") {
+    handle_history(matches)
+  } else if let Some(matches) = matches.subcommand_matches("diff") {
+    handle_diff(matches)
+  } else if let Some(matches) = matches.subcommand_matches("rekey") {
+    handle_rekey(matches)
   } else if let Some(matches) = matches.subcommand_matches("ls") {
-    handle_list(matches);
+    handle_list(matches)
   } else {
-    app.print_help().unwrap();
+    app
+      .print_help()
+      .map_err(|err| OError::InvalidArgument(err.message))
   }
 }
 
 fn main() {
-  parse_args();
+  if let Err(err) = parse_args() {
+    eprintln!("{}", err);
+    std::process::exit(err.exit_code());
+  }
 }
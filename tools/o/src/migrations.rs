@@ -0,0 +1,73 @@
+use serde_json::{json, Value};
+
+use crate::data::{migrate_record_dates, Record};
+
+// Version of the o_data format written by this build. When the records change
+// in an incompatible way, bump it and add a function to `MIGRATIONS`.
+pub const DATA_VERSION: u64 = 1;
+
+// Layout of o_data since the version 1
+#[derive(Debug, Serialize)]
+pub struct DataFile<'a> {
+  pub version: u64,
+  pub records: &'a [Record],
+}
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+// `MIGRATIONS[n]` takes the data from the version n to n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+// The version 0 is the bare array of records, with `dd/mm/yy` dates
+fn migrate_v0_to_v1(data: Value) -> Result<Value, String> {
+  let mut records: Vec<Record> = serde_json::from_value(data).map_err(|err| err.to_string())?;
+
+  migrate_record_dates(&mut records);
+
+  Ok(json!({ "version": 1, "records": records }))
+}
+
+fn get_version(data: &Value) -> Result<u64, String> {
+  match data {
+    Value::Array(_) => Ok(0),
+    Value::Object(object) => object
+      .get("version")
+      .and_then(Value::as_u64)
+      .ok_or_else(|| "the header has no valid version".to_string()),
+    _ => Err("expected an object with a version and the records".to_string()),
+  }
+}
+
+pub fn get_data_file(records: &[Record]) -> DataFile<'_> {
+  DataFile {
+    version: DATA_VERSION,
+    records,
+  }
+}
+
+// Applies the migrations from the version of the file to the current one
+pub fn parse_data_json(data: Value) -> Result<Vec<Record>, String> {
+  let mut data = data;
+  let mut version = get_version(&data)?;
+
+  if version > DATA_VERSION {
+    return Err(format!(
+      "the version {} is newer than the {} supported by this build of o, update it",
+      version, DATA_VERSION
+    ));
+  }
+
+  while version < DATA_VERSION {
+    data = MIGRATIONS[version as usize](data)
+      .map_err(|err| format!("unable to migrate from the version {}: {}", version, err))?;
+    version += 1;
+  }
+
+  match data {
+    Value::Object(mut object) => {
+      serde_json::from_value(object.remove("records").unwrap_or(Value::Null))
+        .map_err(|err| format!("invalid records: {}", err))
+    }
+    _ => Err("expected an object with a version and the records".to_string()),
+  }
+}
@@ -0,0 +1,324 @@
+use serde::Serialize;
+use serde_derive::Serialize;
+
+use o::check::Issue;
+use o::diff::DataDiff;
+use o::journal::JournalEntry;
+use o::store::{LocationCount, PathItem, Stats, TreeItem};
+use o::{Context, OError, OResult, Record};
+
+// Structured output for scripts, `Text` keeps the output of `printing`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+  Text,
+  Json,
+  Jsonl,
+  Csv,
+  Tsv,
+}
+
+impl OutputFormat {
+  pub fn parse(format: &str) -> OResult<Self> {
+    match format {
+      "text" => Ok(OutputFormat::Text),
+      "json" => Ok(OutputFormat::Json),
+      "jsonl" => Ok(OutputFormat::Jsonl),
+      "csv" => Ok(OutputFormat::Csv),
+      "tsv" => Ok(OutputFormat::Tsv),
+      _ => Err(OError::InvalidArgument(format!(
+        "Unknown format {}, expected one of: text, json, jsonl, csv, tsv",
+        format
+      ))),
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct RecordRow<'a> {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  change: Option<&'static str>,
+  what_id: usize,
+  what: &'a str,
+  location_id: usize,
+  location: &'a str,
+  path: String,
+  created: &'a str,
+  updated: &'a str,
+  notes: &'a str,
+  quantity: Option<usize>,
+  min_quantity: Option<usize>,
+  tags: String,
+  attributes: String,
+}
+
+#[derive(Serialize)]
+struct TreeRow<'a> {
+  id: usize,
+  name: &'a str,
+  parent_id: Option<usize>,
+  depth: usize,
+  count: usize,
+}
+
+#[derive(Serialize)]
+struct JournalEntryRow<'a> {
+  number: usize,
+  timestamp: &'a str,
+  command: &'a str,
+  changes: usize,
+  undone: bool,
+}
+
+#[derive(Serialize)]
+struct IssueRow<'a> {
+  kind: &'static str,
+  description: &'a str,
+  fixable: bool,
+  what_ids: String,
+}
+
+#[derive(Serialize)]
+struct StatsRow {
+  count: usize,
+  root_nodes: usize,
+  quantity: usize,
+  low_stock: usize,
+  tags: String,
+  containers: String,
+}
+
+#[derive(Serialize)]
+struct MessageRow<'a> {
+  message: &'a str,
+}
+
+fn get_output_error<E: ToString>(err: E) -> OError {
+  OError::Io(std::io::Error::other(err.to_string()))
+}
+
+fn print_rows<T: Serialize>(rows: &[T], format: OutputFormat) -> OResult<()> {
+  match format {
+    OutputFormat::Text | OutputFormat::Json => {
+      println!(
+        "{}",
+        serde_json::to_string_pretty(rows).map_err(get_output_error)?
+      );
+    }
+    OutputFormat::Jsonl => {
+      for row in rows {
+        println!("{}", serde_json::to_string(row).map_err(get_output_error)?);
+      }
+    }
+    OutputFormat::Csv | OutputFormat::Tsv => {
+      let mut wtr = csv::WriterBuilder::new()
+        .delimiter(if format == OutputFormat::Csv {
+          b','
+        } else {
+          b'\t'
+        })
+        .from_writer(std::io::stdout());
+
+      for row in rows {
+        wtr.serialize(row).map_err(get_output_error)?;
+      }
+
+      wtr.flush()?;
+    }
+  }
+
+  Ok(())
+}
+
+// With json a single value is printed as an object instead of an array
+fn print_row<T: Serialize>(row: &T, format: OutputFormat) -> OResult<()> {
+  match format {
+    OutputFormat::Json => {
+      println!(
+        "{}",
+        serde_json::to_string_pretty(row).map_err(get_output_error)?
+      );
+
+      Ok(())
+    }
+    _ => print_rows(&[row], format),
+  }
+}
+
+fn get_record_row<'a>(
+  record: &'a Record,
+  context: &Context,
+  change: Option<&'static str>,
+) -> RecordRow<'a> {
+  RecordRow {
+    change,
+    what_id: record.what_id,
+    what: &record.what,
+    location_id: record.location_id,
+    location: &record.location,
+    path: context.get_path_names(record.location_id).join(" > "),
+    created: &record.created,
+    updated: &record.updated,
+    notes: &record.notes,
+    quantity: record.quantity,
+    min_quantity: record.min_quantity,
+    tags: record.tags.join(","),
+    attributes: record
+      .attributes
+      .iter()
+      .map(|(key, value)| format!("{}={}", key, value))
+      .collect::<Vec<String>>()
+      .join(";"),
+  }
+}
+
+pub fn print_records(records: &[&Record], context: &Context, format: OutputFormat) -> OResult<()> {
+  let rows: Vec<RecordRow> = records
+    .iter()
+    .map(|record| get_record_row(record, context, None))
+    .collect();
+
+  print_rows(&rows, format)
+}
+
+// Used by `diff` and by the write commands to describe what they changed,
+// the removed records keep their last version
+pub fn print_diff(diff: &DataDiff, context: &Context, format: OutputFormat) -> OResult<()> {
+  let rows: Vec<RecordRow> = diff
+    .added
+    .iter()
+    .map(|record| get_record_row(record, context, Some("added")))
+    .chain(
+      diff
+        .removed
+        .iter()
+        .map(|record| get_record_row(record, context, Some("removed"))),
+    )
+    .chain(
+      diff
+        .changed
+        .iter()
+        .map(|changed| get_record_row(&changed.after, context, Some("changed"))),
+    )
+    .collect();
+
+  print_rows(&rows, format)
+}
+
+pub fn print_locations(locations: &[&LocationCount], format: OutputFormat) -> OResult<()> {
+  print_rows(locations, format)
+}
+
+// Only the json output keeps the tag counts and the containers as lists
+pub fn print_stats(stats: &Stats, format: OutputFormat) -> OResult<()> {
+  if format == OutputFormat::Json {
+    return print_row(stats, format);
+  }
+
+  let row = StatsRow {
+    count: stats.count,
+    root_nodes: stats.root_nodes,
+    quantity: stats.quantity,
+    low_stock: stats.low_stock,
+    tags: stats
+      .tags
+      .iter()
+      .map(|tag| format!("{}:{}", tag.name, tag.count))
+      .collect::<Vec<String>>()
+      .join(","),
+    containers: stats
+      .containers
+      .iter()
+      .map(|container| format!("{}:{}", container.id, container.quantity.unwrap_or(0)))
+      .collect::<Vec<String>>()
+      .join(","),
+  };
+
+  print_row(&row, format)
+}
+
+pub fn print_path(path: &[PathItem], format: OutputFormat) -> OResult<()> {
+  print_rows(path, format)
+}
+
+// The json output keeps the nesting, the rest of formats have one row per node
+pub fn print_tree(items: &[TreeItem], format: OutputFormat) -> OResult<()> {
+  fn add_rows<'a>(
+    item: &'a TreeItem,
+    parent_id: Option<usize>,
+    depth: usize,
+    rows: &mut Vec<TreeRow<'a>>,
+  ) {
+    rows.push(TreeRow {
+      id: item.id,
+      name: &item.name,
+      parent_id,
+      depth,
+      count: item.count,
+    });
+
+    for child in &item.children {
+      add_rows(child, Some(item.id), depth + 1, rows);
+    }
+  }
+
+  if format == OutputFormat::Json {
+    return print_rows(items, format);
+  }
+
+  let mut rows: Vec<TreeRow> = vec![];
+
+  for item in items {
+    add_rows(item, None, 0, &mut rows);
+  }
+
+  print_rows(&rows, format)
+}
+
+// Each entry comes with its number in the history and if it's undone
+pub fn print_journal_entries(
+  entries: &[(usize, &JournalEntry, bool)],
+  format: OutputFormat,
+) -> OResult<()> {
+  let rows: Vec<JournalEntryRow> = entries
+    .iter()
+    .map(|(number, entry, is_undone)| JournalEntryRow {
+      number: *number,
+      timestamp: &entry.timestamp,
+      command: &entry.command,
+      changes: entry.changes.len(),
+      undone: *is_undone,
+    })
+    .collect();
+
+  print_rows(&rows, format)
+}
+
+pub fn print_issues(issues: &[Issue], format: OutputFormat) -> OResult<()> {
+  let rows: Vec<IssueRow> = issues
+    .iter()
+    .map(|issue| IssueRow {
+      kind: issue.kind.get_label(),
+      description: &issue.description,
+      fixable: issue.fixable,
+      what_ids: issue
+        .records
+        .iter()
+        .map(|record| record.what_id.to_string())
+        .collect::<Vec<String>>()
+        .join(" "),
+    })
+    .collect();
+
+  print_rows(&rows, format)
+}
+
+pub fn print_message(message: &str, format: OutputFormat) -> OResult<()> {
+  match format {
+    OutputFormat::Text => {
+      println!("{}", message);
+
+      Ok(())
+    }
+    _ => print_row(&MessageRow { message }, format),
+  }
+}
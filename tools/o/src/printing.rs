@@ -0,0 +1,323 @@
+use chrono::Local;
+
+use o::attributes::format_attributes;
+use o::check::Issue;
+use o::data::{format_record_date, get_changed_fields, get_is_low_stock, parse_record_timestamp};
+use o::diff::DataDiff;
+use o::journal::{JournalEntry, RecordChange};
+use o::store::{
+  get_path_items, LocationCount, MergeSummary, PathItem, StaleGroup, Stats, TreeItem,
+};
+use o::Context;
+use o::Record;
+
+fn format_quantity(record: &Record) -> String {
+  let quantity = match record.quantity {
+    Some(quantity) => quantity,
+    None => return String::new(),
+  };
+
+  match record.min_quantity {
+    Some(min_quantity) if get_is_low_stock(record) => {
+      format!(" | qty: {} (min {}, low stock)", quantity, min_quantity)
+    }
+    Some(min_quantity) => format!(" | qty: {} (min {})", quantity, min_quantity),
+    None => format!(" | qty: {}", quantity),
+  }
+}
+
+fn format_tags(record: &Record) -> String {
+  if record.tags.is_empty() {
+    return String::new();
+  }
+
+  format!(" | tags: {}", record.tags.join(", "))
+}
+
+fn format_record_attributes(record: &Record) -> String {
+  if record.attributes.is_empty() {
+    return String::new();
+  }
+
+  format!(" | {}", format_attributes(&record.attributes))
+}
+
+fn format_record(record: &Record, date_format: &str) -> String {
+  format!(
+    "{} [{}] | {} [{}] | {} | {}{}{}{}",
+    record.what,
+    record.what_id,
+    record.location,
+    record.location_id,
+    format_record_date(&record.updated, date_format),
+    record.notes,
+    format_quantity(record),
+    format_tags(record),
+    format_record_attributes(record)
+  )
+}
+
+pub fn print_record_line(record: &Record, date_format: &str) {
+  println!("- {}", format_record(record, date_format));
+}
+
+// Only the last item of the path displays its id
+fn format_path(path: &[PathItem]) -> String {
+  let names: Vec<&str> = path.iter().map(|item| item.name.as_str()).collect();
+
+  match path.last() {
+    Some(item) => format!("{} [{}]", names.join(" > "), item.id),
+    None => String::new(),
+  }
+}
+
+pub fn print_path(path: &[PathItem]) {
+  println!("{}", format_path(path));
+}
+
+// Like `print_record_line`, with the full path instead of the location
+pub fn print_record_path_line(record: &Record, context: &Context, date_format: &str) {
+  let path = get_path_items(context, record.what_id);
+
+  println!(
+    "- {} | {} | {}{}{}{}",
+    format_path(&path),
+    format_record_date(&record.updated, date_format),
+    record.notes,
+    format_quantity(record),
+    format_tags(record),
+    format_record_attributes(record)
+  );
+}
+
+// The container with its full path, and each item with its age
+pub fn print_stale_group(group: &StaleGroup, context: &Context, date_format: &str) {
+  println!(
+    "{} <{} stale items>",
+    format_path(&get_path_items(context, group.id)),
+    group.records.len()
+  );
+
+  for record in &group.records {
+    let age = match parse_record_timestamp(&record.updated) {
+      Some(updated) => format!("{} days ago", (Local::now() - updated).num_days()),
+      None => "unknown date".to_string(),
+    };
+
+    println!(
+      "    - {} [{}] | {} | {}",
+      record.what,
+      record.what_id,
+      format_record_date(&record.updated, date_format),
+      age
+    );
+  }
+}
+
+pub fn print_merge_summary(summary: &MergeSummary) {
+  println!("Merge summary:");
+  println!("- Inserted: {}", summary.inserted);
+  println!("- Updated: {}", summary.updated);
+  println!("- Unchanged: {}", summary.unchanged);
+
+  if summary.skipped > 0 {
+    println!("- Skipped conflicts: {}", summary.skipped);
+  }
+}
+
+pub fn print_location_with_count(location: &LocationCount) {
+  print!("- {}", &location.name);
+  print!(" [{}]", &location.id);
+  print!(" <{} items>", location.count);
+
+  if let Some(quantity) = location.quantity {
+    print!(" <{} units>", quantity);
+  }
+
+  println!();
+}
+
+pub fn print_stats(stats: &Stats) {
+  println!("Stats:");
+  println!("- Count: {}", stats.count);
+  println!("- Root nodes: {}", stats.root_nodes);
+
+  if !stats.containers.is_empty() {
+    println!("- Quantity: {}", stats.quantity);
+    println!("- Low stock: {}", stats.low_stock);
+    println!("- Quantity per container:");
+
+    for container in &stats.containers {
+      println!(
+        "    - {} [{}]: {}",
+        container.name,
+        container.id,
+        container.quantity.unwrap_or(0)
+      );
+    }
+  }
+
+  if !stats.tags.is_empty() {
+    println!("- Tags:");
+
+    for tag in &stats.tags {
+      println!("    - {}: {}", tag.name, tag.count);
+    }
+  }
+}
+
+pub fn print_tree(items: &[TreeItem]) {
+  fn print_recursive(item: &TreeItem, prefix: &str, connector: &str, child_prefix: &str) {
+    if item.count == 0 {
+      println!("{}{}{} [{}]", prefix, connector, item.name, item.id);
+    } else {
+      println!(
+        "{}{}{} [{}] <{} items>",
+        prefix, connector, item.name, item.id, item.count
+      );
+    }
+
+    let prefix = format!("{}{}", prefix, child_prefix);
+
+    for (idx, child) in item.children.iter().enumerate() {
+      if idx + 1 == item.children.len() {
+        print_recursive(child, &prefix, "└── ", "    ");
+      } else {
+        print_recursive(child, &prefix, "├── ", "│   ");
+      }
+    }
+  }
+
+  for item in items {
+    print_recursive(item, "", "", "");
+  }
+}
+
+pub fn print_ascii_tree(items: &[TreeItem]) {
+  fn print_recursive(item: &TreeItem, depth: usize) -> usize {
+    let mut last_depth = depth;
+    let prefix = if item.children.is_empty() { "-" } else { "+" };
+
+    println!(
+      "{}{} {} [{}]",
+      " ".repeat(depth * 6),
+      prefix,
+      item.name,
+      item.id
+    );
+
+    for (idx, child) in item.children.iter().enumerate() {
+      if idx == 0 {
+        last_depth = depth + 1;
+      }
+
+      if last_depth != depth + 1 {
+        println!();
+      }
+
+      last_depth = print_recursive(child, depth + 1);
+    }
+
+    last_depth
+  }
+
+  println!("<top>");
+
+  let mut depth = 1;
+  for item in items {
+    if depth != 1 {
+      println!();
+    }
+
+    depth = print_recursive(item, 1);
+  }
+}
+
+fn print_record_change(change: &RecordChange) {
+  match (&change.before, &change.after) {
+    (None, Some(after)) => println!("    + {} [{}]", after.record.what, after.record.what_id),
+    (Some(before), None) => println!("    - {} [{}]", before.record.what, before.record.what_id),
+    (Some(before), Some(after)) => {
+      println!("    ~ {} [{}]", after.record.what, after.record.what_id);
+
+      for field_change in get_changed_fields(&before.record, &after.record) {
+        println!(
+          "        {}: {} → {}",
+          field_change.field, field_change.before, field_change.after
+        );
+      }
+    }
+    (None, None) => {}
+  }
+}
+
+pub fn print_journal_entry(
+  number: usize,
+  entry: &JournalEntry,
+  is_undone: bool,
+  date_format: &str,
+) {
+  println!(
+    "[{}] {} | {}{}",
+    number,
+    format_record_date(&entry.timestamp, date_format),
+    entry.command,
+    if is_undone { " (undone)" } else { "" }
+  );
+
+  for change in &entry.changes {
+    print_record_change(change);
+  }
+}
+
+pub fn print_diff(diff: &DataDiff, date_format: &str) {
+  if diff.is_empty() {
+    println!("No differences");
+    return;
+  }
+
+  for record in &diff.added {
+    println!("+ {}", format_record(record, date_format));
+  }
+
+  for record in &diff.removed {
+    println!("- {}", format_record(record, date_format));
+  }
+
+  for changed in &diff.changed {
+    println!("~ {} [{}]", changed.after.what, changed.after.what_id);
+
+    for field_change in &changed.fields {
+      let is_location_field =
+        field_change.field == "location" || field_change.field == "location_id";
+
+      if changed.location_move.is_none() || !is_location_field {
+        println!(
+          "    {}: {} → {}",
+          field_change.field, field_change.before, field_change.after
+        );
+      }
+    }
+
+    if let Some(location_move) = &changed.location_move {
+      println!(
+        "    location: {} → {}",
+        location_move.before.join(" > "),
+        location_move.after.join(" > ")
+      );
+    }
+  }
+}
+
+pub fn print_issue(issue: &Issue, date_format: &str) {
+  println!(
+    "{}: {}{}",
+    issue.kind.get_label(),
+    issue.description,
+    if issue.fixable { " (fixable)" } else { "" }
+  );
+
+  for record in &issue.records {
+    println!("    - {}", format_record(record, date_format));
+  }
+}
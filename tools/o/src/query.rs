@@ -0,0 +1,448 @@
+use chrono::NaiveDate;
+use regex::{Regex, RegexBuilder};
+
+use crate::attributes::{parse_date, AttributeValue};
+use crate::data::{parse_record_date, Record};
+use crate::errors::{OError, OResult};
+
+// Query syntax, the terms are joined with AND by default:
+//   what:/^usb/ notes:warranty location:garage updated:<2024-01-01
+//   (what:drill OR what:saw) AND NOT location:"tool box" id:>=10 tag:winter
+// Texts are matched ignoring the case, as well as the /regex/ patterns.
+// Terms without a field are matched with what, location and notes, and the
+// rest of fields are attributes: price:>=20 bought:<2024-01-01 serial:xyz
+#[derive(Debug, Clone, PartialEq)]
+enum Field {
+  Any,
+  What,
+  Location,
+  Notes,
+  Id,
+  LocationId,
+  Created,
+  Updated,
+  Tag,
+  Quantity,
+  MinQuantity,
+  Attribute(String),
+}
+
+const FIELD_NAMES: &[(&str, Field)] = &[
+  ("what", Field::What),
+  ("location", Field::Location),
+  ("notes", Field::Notes),
+  ("id", Field::Id),
+  ("location_id", Field::LocationId),
+  ("created", Field::Created),
+  ("updated", Field::Updated),
+  ("tag", Field::Tag),
+  ("quantity", Field::Quantity),
+  ("min_quantity", Field::MinQuantity),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+  Less,
+  LessOrEqual,
+  Equal,
+  GreaterOrEqual,
+  Greater,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+  Text(String),
+  Pattern(Regex),
+  Number(Comparison, usize),
+  Date(Comparison, NaiveDate),
+  // compared depending on the type of the attribute in each record
+  Value(Option<Comparison>, String),
+}
+
+#[derive(Debug, Clone)]
+enum Expression {
+  Term(Field, Matcher),
+  Not(Box<Expression>),
+  And(Vec<Expression>),
+  Or(Vec<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  And,
+  Or,
+  Not,
+  Term(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Query {
+  expression: Expression,
+}
+
+fn get_token_text(token: &Token) -> &str {
+  match token {
+    Token::Open => "(",
+    Token::Close => ")",
+    Token::And => "AND",
+    Token::Or => "OR",
+    Token::Not => "NOT",
+    Token::Term(word) => word,
+  }
+}
+
+fn get_query_error(details: &str) -> OError {
+  OError::InvalidArgument(format!("Invalid query: {}", details))
+}
+
+// Quoted texts and /patterns/ can contain spaces and parentheses
+fn get_tokens(query: &str) -> OResult<Vec<Token>> {
+  let mut tokens: Vec<Token> = vec![];
+  let mut chars = query.chars().peekable();
+
+  while let Some(c) = chars.peek().cloned() {
+    if c.is_whitespace() {
+      chars.next();
+      continue;
+    }
+
+    if c == '(' || c == ')' {
+      chars.next();
+      tokens.push(if c == '(' { Token::Open } else { Token::Close });
+      continue;
+    }
+
+    let mut word = String::new();
+    let mut is_quoted = false;
+    let mut is_pattern = false;
+
+    while let Some(c) = chars.peek().cloned() {
+      if !is_quoted && !is_pattern && (c.is_whitespace() || c == '(' || c == ')') {
+        break;
+      }
+
+      chars.next();
+
+      if is_pattern && c == '\\' {
+        word.push(c);
+
+        if let Some(escaped) = chars.next() {
+          word.push(escaped);
+        }
+
+        continue;
+      }
+
+      if c == '"' && !is_pattern {
+        is_quoted = !is_quoted;
+      } else if c == '/' && !is_quoted && (is_pattern || word.is_empty() || word.ends_with(':')) {
+        is_pattern = !is_pattern;
+      }
+
+      word.push(c);
+    }
+
+    if is_quoted || is_pattern {
+      return Err(get_query_error(&format!("{} is not closed", word)));
+    }
+
+    tokens.push(match word.as_str() {
+      "AND" => Token::And,
+      "OR" => Token::Or,
+      "NOT" => Token::Not,
+      _ => Token::Term(word),
+    });
+  }
+
+  Ok(tokens)
+}
+
+fn parse_comparison(value: &str) -> (Option<Comparison>, &str) {
+  let prefixes = [
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+    ("=", Comparison::Equal),
+  ];
+
+  for (prefix, comparison) in prefixes.iter() {
+    if let Some(rest) = value.strip_prefix(prefix) {
+      return (Some(*comparison), rest);
+    }
+  }
+
+  (None, value)
+}
+
+fn parse_term(word: &str) -> OResult<Expression> {
+  let (field, value) = match word.find(':') {
+    Some(idx) if !word.starts_with('"') && !word.starts_with('/') => {
+      let name = &word[..idx];
+
+      // the rest of names are attributes
+      match FIELD_NAMES
+        .iter()
+        .find(|(field_name, _)| *field_name == name)
+      {
+        Some((_, field)) => (field.clone(), &word[idx + 1..]),
+        None => (Field::Attribute(name.to_lowercase()), &word[idx + 1..]),
+      }
+    }
+    _ => (Field::Any, word),
+  };
+
+  if value.len() >= 2 && value.starts_with('/') && value.ends_with('/') {
+    let pattern = RegexBuilder::new(&value[1..value.len() - 1])
+      .case_insensitive(true)
+      .build()
+      .map_err(|err| get_query_error(&err.to_string()))?;
+
+    return Ok(Expression::Term(field, Matcher::Pattern(pattern)));
+  }
+
+  let value = value.trim_matches('"');
+
+  let matcher = match &field {
+    Field::Id | Field::LocationId | Field::Quantity | Field::MinQuantity => {
+      let (comparison, number) = parse_comparison(value);
+      let number = number
+        .parse::<usize>()
+        .map_err(|_| get_query_error(&format!("{} is not a number", value)))?;
+
+      Matcher::Number(comparison.unwrap_or(Comparison::Equal), number)
+    }
+    Field::Created | Field::Updated => {
+      let (comparison, date) = parse_comparison(value);
+      let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| get_query_error(&format!("{} is not a date like 2024-01-31", value)))?;
+
+      Matcher::Date(comparison.unwrap_or(Comparison::Equal), date)
+    }
+    Field::Attribute(_) => {
+      let (comparison, value) = parse_comparison(value);
+
+      Matcher::Value(comparison, value.to_string())
+    }
+    _ => Matcher::Text(value.to_lowercase()),
+  };
+
+  Ok(Expression::Term(field, matcher))
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  position: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position)
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.position).cloned();
+
+    self.position += 1;
+
+    token
+  }
+
+  fn parse_or(&mut self) -> OResult<Expression> {
+    let mut expressions = vec![self.parse_and()?];
+
+    while self.peek() == Some(&Token::Or) {
+      self.next();
+      expressions.push(self.parse_and()?);
+    }
+
+    Ok(if expressions.len() == 1 {
+      expressions.remove(0)
+    } else {
+      Expression::Or(expressions)
+    })
+  }
+
+  fn parse_and(&mut self) -> OResult<Expression> {
+    let mut expressions = vec![self.parse_not()?];
+
+    loop {
+      match self.peek() {
+        Some(Token::And) => {
+          self.next();
+        }
+        Some(Token::Open) | Some(Token::Not) | Some(Token::Term(_)) => {}
+        _ => break,
+      }
+
+      expressions.push(self.parse_not()?);
+    }
+
+    Ok(if expressions.len() == 1 {
+      expressions.remove(0)
+    } else {
+      Expression::And(expressions)
+    })
+  }
+
+  fn parse_not(&mut self) -> OResult<Expression> {
+    if self.peek() == Some(&Token::Not) {
+      self.next();
+
+      return Ok(Expression::Not(Box::new(self.parse_not()?)));
+    }
+
+    match self.next() {
+      Some(Token::Open) => {
+        let expression = self.parse_or()?;
+
+        match self.next() {
+          Some(Token::Close) => Ok(expression),
+          _ => Err(get_query_error("missing )")),
+        }
+      }
+      Some(Token::Term(word)) => parse_term(&word),
+      Some(token) => Err(get_query_error(&format!(
+        "unexpected {}",
+        get_token_text(&token)
+      ))),
+      None => Err(get_query_error("unexpected end")),
+    }
+  }
+}
+
+fn get_is_ordered<T: PartialOrd>(value: T, comparison: Comparison, expected: T) -> bool {
+  match comparison {
+    Comparison::Less => value < expected,
+    Comparison::LessOrEqual => value <= expected,
+    Comparison::Equal => value == expected,
+    Comparison::GreaterOrEqual => value >= expected,
+    Comparison::Greater => value > expected,
+  }
+}
+
+fn get_is_text_match(matcher: &Matcher, text: &str) -> bool {
+  match matcher {
+    Matcher::Text(value) => text.to_lowercase().contains(value),
+    Matcher::Pattern(pattern) => pattern.is_match(text),
+    _ => false,
+  }
+}
+
+// Numbers, dates and bools are compared by value, strings without a
+// comparison are matched like the rest of texts
+fn get_is_attribute_match(matcher: &Matcher, attribute: &AttributeValue) -> bool {
+  let (comparison, value) = match matcher {
+    Matcher::Value(comparison, value) => (comparison, value),
+    _ => return get_is_text_match(matcher, &attribute.to_string()),
+  };
+
+  match attribute {
+    AttributeValue::Number(number) => value
+      .parse::<f64>()
+      .is_ok_and(|value| get_is_ordered(*number, comparison.unwrap_or(Comparison::Equal), value)),
+    AttributeValue::Date(date) => match (parse_date(date), parse_date(value)) {
+      (Some(date), Some(value)) => {
+        get_is_ordered(date, comparison.unwrap_or(Comparison::Equal), value)
+      }
+      _ => false,
+    },
+    AttributeValue::Bool(flag) => {
+      *comparison != Some(Comparison::Less)
+        && *comparison != Some(Comparison::Greater)
+        && value.parse::<bool>() == Ok(*flag)
+    }
+    // strings like 00123 are still compared as numbers
+    AttributeValue::String(text) => match (comparison, text.parse::<f64>(), value.parse::<f64>()) {
+      (None, _, _) => text.to_lowercase().contains(&value.to_lowercase()),
+      (Some(comparison), Ok(number), Ok(value)) => get_is_ordered(number, *comparison, value),
+      (Some(comparison), _, _) => {
+        get_is_ordered(text.to_lowercase(), *comparison, value.to_lowercase())
+      }
+    },
+  }
+}
+
+fn get_is_term_match(field: &Field, matcher: &Matcher, record: &Record) -> bool {
+  match (field, matcher) {
+    (Field::Id, Matcher::Number(comparison, id)) => {
+      get_is_ordered(record.what_id, *comparison, *id)
+    }
+    (Field::LocationId, Matcher::Number(comparison, id)) => {
+      get_is_ordered(record.location_id, *comparison, *id)
+    }
+    // the items that don't track their quantity never match
+    (Field::Quantity, Matcher::Number(comparison, quantity)) => record
+      .quantity
+      .is_some_and(|value| get_is_ordered(value, *comparison, *quantity)),
+    (Field::MinQuantity, Matcher::Number(comparison, quantity)) => record
+      .min_quantity
+      .is_some_and(|value| get_is_ordered(value, *comparison, *quantity)),
+    (Field::Created, Matcher::Date(comparison, date)) => parse_record_date(&record.created)
+      .is_some_and(|created| get_is_ordered(created, *comparison, *date)),
+    (Field::Updated, Matcher::Date(comparison, date)) => parse_record_date(&record.updated)
+      .is_some_and(|updated| get_is_ordered(updated, *comparison, *date)),
+    (Field::Quantity, _) => record
+      .quantity
+      .is_some_and(|value| get_is_text_match(matcher, &value.to_string())),
+    (Field::MinQuantity, _) => record
+      .min_quantity
+      .is_some_and(|value| get_is_text_match(matcher, &value.to_string())),
+    (Field::Id, _) => get_is_text_match(matcher, &record.what_id.to_string()),
+    (Field::LocationId, _) => get_is_text_match(matcher, &record.location_id.to_string()),
+    (Field::Created, _) => get_is_text_match(matcher, &record.created),
+    (Field::Updated, _) => get_is_text_match(matcher, &record.updated),
+    (Field::What, _) => get_is_text_match(matcher, &record.what),
+    (Field::Location, _) => get_is_text_match(matcher, &record.location),
+    (Field::Notes, _) => get_is_text_match(matcher, &record.notes),
+    (Field::Attribute(key), _) => record
+      .attributes
+      .get(key)
+      .is_some_and(|attribute| get_is_attribute_match(matcher, attribute)),
+    (Field::Tag, _) => record
+      .tags
+      .iter()
+      .any(|tag| get_is_text_match(matcher, tag)),
+    (Field::Any, _) => [&record.what, &record.location, &record.notes]
+      .iter()
+      .any(|text| get_is_text_match(matcher, text)),
+  }
+}
+
+fn get_is_match(expression: &Expression, record: &Record) -> bool {
+  match expression {
+    Expression::Term(field, matcher) => get_is_term_match(field, matcher, record),
+    Expression::Not(expression) => !get_is_match(expression, record),
+    Expression::And(expressions) => expressions
+      .iter()
+      .all(|expression| get_is_match(expression, record)),
+    Expression::Or(expressions) => expressions
+      .iter()
+      .any(|expression| get_is_match(expression, record)),
+  }
+}
+
+impl Query {
+  pub fn parse(query: &str) -> OResult<Self> {
+    let mut parser = Parser {
+      tokens: get_tokens(query)?,
+      position: 0,
+    };
+    let expression = parser.parse_or()?;
+
+    if let Some(token) = parser.peek() {
+      return Err(get_query_error(&format!(
+        "unexpected {}",
+        get_token_text(token)
+      )));
+    }
+
+    Ok(Self { expression })
+  }
+
+  pub fn matches(&self, record: &Record) -> bool {
+    get_is_match(&self.expression, record)
+  }
+}
@@ -1,77 +1,225 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::fs::{copy, read_dir, remove_file, rename, DirBuilder, File, OpenOptions};
+use std::fs::{read, read_dir, rename, DirBuilder, File, OpenOptions};
 use std::io::prelude::*;
-use std::io::BufReader;
+use std::path::Path;
 
-use clap::ArgMatches;
-use csv::ReaderBuilder;
+use csv::{ReaderBuilder, WriterBuilder};
 use dirs::home_dir;
 
-use crate::data::{Config, Record};
+use crate::attributes::{
+  parse_attribute_key, parse_attribute_value, AttributeType, AttributeValue, Attributes,
+  AttributesSchema,
+};
+use crate::data::{
+  get_context, get_empty_notes_text, get_now_date, parse_import_date, Config, Context, Record,
+};
+use crate::encryption::{decrypt, encrypt, generate_key, get_is_encrypted};
+use crate::errors::{OError, OResult};
+use crate::journal::Journal;
+use crate::migrations::{get_data_file, parse_data_json, DATA_VERSION};
+use crate::store::MergeFields;
+
+// The columns that are not in `CSV_COLUMNS` are the attributes, as `key` or as
+// `key:type` when the type can't be inferred from the values. The path of the
+// location is only exported, it's ignored by the import.
+const CSV_COLUMNS: &[&str] = &[
+  "what_id",
+  "what",
+  "location_id",
+  "location",
+  "created",
+  "updated",
+  "notes",
+  "tags",
+  "quantity",
+  "min_quantity",
+];
+
+const CSV_PATH_COLUMN: &str = "path";
+
+// Without a header the columns are `columns`, or `CSV_COLUMNS` by default
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+  pub columns: Option<Vec<String>>,
+  pub delimiter: u8,
+  pub has_header: bool,
+  pub with_path: bool,
+}
+
+impl Default for CsvOptions {
+  fn default() -> Self {
+    Self {
+      columns: None,
+      delimiter: b',',
+      has_header: true,
+      with_path: false,
+    }
+  }
+}
+
+fn parse_csv_number(column: &str, value: &str) -> OResult<Option<usize>> {
+  if value.is_empty() {
+    return Ok(None);
+  }
+
+  value.parse::<usize>().map(Some).map_err(|_| {
+    OError::InvalidArgument(format!(
+      "The {} column must be a positive number, found: {}",
+      column, value
+    ))
+  })
+}
+
+fn format_csv_number(number: Option<usize>) -> String {
+  number.map_or_else(String::new, |number| number.to_string())
+}
+
+fn parse_csv_column(column: &str) -> OResult<String> {
+  let column = column.trim().to_lowercase();
+
+  if CSV_COLUMNS.contains(&column.as_str()) || column == CSV_PATH_COLUMN {
+    return Ok(column);
+  }
+
+  match column.split_once(':') {
+    Some((key, type_name)) => match AttributeType::parse(type_name) {
+      Some(_) => Ok([&parse_attribute_key(key)?, ":", type_name].concat()),
+      None => Err(OError::InvalidArgument(format!(
+        "Unknown attribute type in the column {}, expected one of: string, number, date, bool",
+        column
+      ))),
+    },
+    None => parse_attribute_key(&column),
+  }
+}
+
+fn get_is_attribute_column(column: &str) -> bool {
+  !CSV_COLUMNS.contains(&column) && column != CSV_PATH_COLUMN
+}
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-struct CSVRecord {
-  what: String,
-  location: String,
-  updated: String,
-  notes: String,
+// The key and the type of the `key:type` columns
+fn split_attribute_column(column: &str) -> (&str, Option<AttributeType>) {
+  match column.split_once(':') {
+    Some((key, type_name)) => (key, AttributeType::parse(type_name)),
+    None => (column, None),
+  }
+}
+
+// The type is added when some value would be read with another one
+fn get_attribute_column(records: &[Record], key: &str) -> String {
+  let values: Vec<&AttributeValue> = records
+    .iter()
+    .filter_map(|record| record.attributes.get(key))
+    .collect();
+  let is_inferred = values.iter().all(|value| {
+    parse_attribute_value(key, &value.to_string(), None)
+      .ok()
+      .as_ref()
+      == Some(*value)
+  });
+
+  match values.first() {
+    Some(value) if !is_inferred => [key, ":", value.get_type().get_name()].concat(),
+    _ => key.to_string(),
+  }
 }
 
-fn get_data_records() -> Vec<Record> {
-  let mut file = File::open(".o/o_data");
+// By default all the columns with the attributes of the records, sorted
+fn get_csv_columns(records: &[Record], options: &CsvOptions) -> OResult<Vec<String>> {
+  let mut columns: Vec<String> = match &options.columns {
+    Some(columns) => columns
+      .iter()
+      .map(|column| parse_csv_column(column))
+      .collect::<OResult<Vec<String>>>()?,
+    None => {
+      let mut attribute_keys: Vec<String> = records
+        .iter()
+        .flat_map(|record| record.attributes.keys().cloned())
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+
+      attribute_keys.sort();
+
+      CSV_COLUMNS
+        .iter()
+        .map(|column| column.to_string())
+        .chain(
+          attribute_keys
+            .iter()
+            .map(|key| get_attribute_column(records, key)),
+        )
+        .collect()
+    }
+  };
 
-  if file.is_err() {
-    file = File::open([home_dir().unwrap().to_str().unwrap(), "/.o/o_data"].concat());
+  if options.with_path && !columns.iter().any(|column| column == CSV_PATH_COLUMN) {
+    columns.push(CSV_PATH_COLUMN.to_string());
   }
 
-  let reader = BufReader::new(file.unwrap());
-  let records: Vec<Record> = serde_json::from_reader(reader).unwrap_or_else(|_| vec![]);
+  Ok(columns)
+}
 
-  records
+fn get_csv_value(record: &Record, column: &str, context: &Context) -> String {
+  match column {
+    "what_id" => record.what_id.to_string(),
+    "what" => record.what.clone(),
+    "location_id" => record.location_id.to_string(),
+    "location" => record.location.clone(),
+    "created" => record.created.clone(),
+    "updated" => record.updated.clone(),
+    "notes" => record.notes.clone(),
+    "tags" => record.tags.join(","),
+    "quantity" => format_csv_number(record.quantity),
+    "min_quantity" => format_csv_number(record.min_quantity),
+    CSV_PATH_COLUMN => context.get_path_names(record.location_id).join(" > "),
+    column => record
+      .attributes
+      .get(split_attribute_column(column).0)
+      .map_or_else(String::new, |value| value.to_string()),
+  }
 }
 
-pub fn init_project() {
+pub fn init_project() -> OResult<()> {
   let project_dir = ".o";
 
-  DirBuilder::new()
-    .recursive(true)
-    .create(project_dir)
-    .unwrap();
+  DirBuilder::new().recursive(true).create(project_dir)?;
 
-  let mut git_ignore_file = File::create([project_dir, "/.gitignore"].concat()).unwrap();
-  let mut config_file = File::create([project_dir, "/o_config.toml"].concat()).unwrap();
+  let mut git_ignore_file = File::create([project_dir, "/.gitignore"].concat())?;
+  let mut config_file = File::create([project_dir, "/o_config.toml"].concat())?;
 
-  File::create([project_dir, "/o_data"].concat()).unwrap();
+  File::create([project_dir, "/o_data"].concat())?;
 
-  git_ignore_file.write_all(b"o_config.toml").unwrap();
-  config_file
-    .write_all(
-      b"encryption_key = \"change_this\"
-saved_actions = 10",
+  git_ignore_file.write_all(b"o_config.toml\nlock\n*.tmp")?;
+  // every project gets its own key, `rekey` changes it
+  config_file.write_all(
+    format!(
+      "encryption_key = \"{}\"
+saved_actions = 10
+date_format = \"%Y-%m-%d %H:%M\"
+",
+      generate_key()
     )
-    .unwrap();
+    .as_bytes(),
+  )?;
 
-  let backups_dir_path = [project_dir, "/backups"].concat();
-
-  DirBuilder::new()
-    .recursive(true)
-    .create(backups_dir_path)
-    .unwrap();
+  Ok(())
 }
 
 fn get_path_exists(path: &str) -> bool {
-  std::path::Path::new(path).exists()
+  Path::new(path).exists()
 }
 
 fn get_config_dir() -> Option<String> {
-  let home_dir_path = [home_dir().unwrap().to_str().unwrap(), "/.o"].concat();
   let local_dir_path = ".o";
 
   if get_path_exists(local_dir_path) {
     return Some(local_dir_path.to_string());
   }
 
+  let home_dir_path = [home_dir()?.to_str()?, "/.o"].concat();
+
   if get_path_exists(&home_dir_path) {
     return Some(home_dir_path);
   }
@@ -79,178 +227,407 @@ fn get_config_dir() -> Option<String> {
   None
 }
 
-fn get_file_to_save_data() -> std::fs::File {
-  let maybe_dir_path = get_config_dir();
+pub(crate) fn get_project_dir() -> OResult<String> {
+  get_config_dir().ok_or(OError::MissingProject)
+}
 
-  if maybe_dir_path.is_none() {
-    println!("There is no .o directory present locally or in the home directory");
-    std::process::exit(1);
+fn encode_data(plain: &[u8], encryption_key: &str) -> Vec<u8> {
+  if encryption_key.is_empty() {
+    return plain.to_vec();
   }
 
-  let dir_path = maybe_dir_path.unwrap();
+  encrypt(plain, encryption_key)
+}
 
-  let saved_actions = get_config().saved_actions;
-  let data_path = [&dir_path, "/o_data"].concat();
-  let backups_dir_path = [&dir_path, "/backups"].concat();
+// Files written before encryption was enabled are still plain JSON
+fn decode_data(contents: &[u8], encryption_key: &str, file_path: &str) -> OResult<Vec<u8>> {
+  if !get_is_encrypted(contents) {
+    return Ok(contents.to_vec());
+  }
 
-  let paths: Vec<String> = read_dir(&backups_dir_path)
-    .unwrap()
-    .map(|x| x.unwrap().path().display().to_string())
-    .collect();
+  if encryption_key.is_empty() {
+    return Err(OError::BadConfig(format!(
+      "{} is encrypted but `encryption_key` in o_config.toml is empty",
+      file_path
+    )));
+  }
 
-  if saved_actions > 0 && paths.len() == saved_actions {
-    let backup_file_path = [
-      &backups_dir_path,
-      "/o_data_prev_",
-      saved_actions.to_string().as_str(),
-    ]
-    .concat();
+  decrypt(contents, encryption_key).ok_or_else(|| OError::WrongKey(file_path.to_string()))
+}
 
-    remove_file(&backup_file_path).ok();
+// After an interrupted `rekey` some files are still encrypted with the previous key
+fn decode_project_data(contents: &[u8], config: &Config, file_path: &str) -> OResult<Vec<u8>> {
+  match (
+    decode_data(contents, &config.encryption_key, file_path),
+    &config.previous_encryption_key,
+  ) {
+    (Err(err), Some(previous_key)) => {
+      decode_data(contents, previous_key, file_path).map_err(|_| err)
+    }
+    (result, _) => result,
   }
+}
 
-  for n in 1..saved_actions {
-    let i = saved_actions - n;
-    let orig_backup_file_path =
-      [&backups_dir_path, "/o_data_prev_", i.to_string().as_str()].concat();
-    let next_backup_file_path = [
-      &backups_dir_path,
-      "/o_data_prev_",
-      (i + 1).to_string().as_str(),
-    ]
-    .concat();
+// The file created by `init` is empty until the first write. Any other
+// content that can't be read stops the command, so a write never replaces
+// the records with an empty list.
+pub fn get_data_records() -> OResult<Vec<Record>> {
+  get_data_records_at(&get_project_dir()?)
+}
 
-    rename(&orig_backup_file_path, &next_backup_file_path).ok();
+// `dir_path` is the `.o` directory of the project
+pub fn get_data_records_at(dir_path: &str) -> OResult<Vec<Record>> {
+  let data_path = [dir_path, "/o_data"].concat();
+  let contents = read(&data_path)?;
+  let plain = decode_project_data(&contents, &get_config_at(dir_path)?, &data_path)?;
+
+  if plain.iter().all(|byte| byte.is_ascii_whitespace()) {
+    return Ok(vec![]);
   }
 
-  if saved_actions > 0 {
-    let backup_file_path = [&backups_dir_path, "/o_data_prev_1"].concat();
+  // an encrypted file can't be fixed by hand until it is decrypted
+  let decrypt_hint = if get_is_encrypted(&contents) {
+    " (the file is encrypted, `o rekey` with an empty key writes it as plain JSON)"
+  } else {
+    ""
+  };
+
+  serde_json::from_slice(&plain)
+    .map_err(|err| err.to_string())
+    .and_then(parse_data_json)
+    .map_err(|err| {
+      OError::CorruptData(format!(
+        "{}: {}. The file was not changed: fix it by hand{} (the format is \
+         {{\"version\": {}, \"records\": [...]}}), `o history` lists the changes of the \
+         latest writes from the journal. Then run `o check`",
+        data_path, err, decrypt_hint, DATA_VERSION
+      ))
+    })
+}
+
+// The temp file lives in the same directory so the final rename is atomic
+fn write_temp_file(file_path: &str, contents: &[u8]) -> OResult<String> {
+  let temp_path = [file_path, ".tmp"].concat();
+  let mut file = OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .open(&temp_path)?;
+
+  file.write_all(contents)?;
+  file.sync_all()?;
 
-    copy(&data_path, &backup_file_path).ok();
+  Ok(temp_path)
+}
+
+// Makes the renames inside the directory durable
+fn sync_parent_dir(file_path: &str) -> OResult<()> {
+  if let Some(dir_path) = Path::new(file_path).parent() {
+    let dir_path = if dir_path.as_os_str().is_empty() {
+      Path::new(".")
+    } else {
+      dir_path
+    };
+
+    File::open(dir_path)?.sync_all()?;
   }
 
-  remove_file(&data_path).ok();
+  Ok(())
+}
 
-  OpenOptions::new()
-    .write(true)
-    .create_new(true)
-    .open(&data_path)
-    .unwrap()
+fn write_file_atomically(file_path: &str, contents: &[u8]) -> OResult<()> {
+  let temp_path = write_temp_file(file_path, contents)?;
+
+  rename(&temp_path, file_path)?;
+  sync_parent_dir(file_path)
 }
 
-pub fn write_all_records(records: &[Record]) {
-  let mut file = get_file_to_save_data();
+// The new data is durable in a temp file before it replaces o_data with a
+// rename, so a crash never leaves it missing. Only `Store` writes, while it
+// holds the project lock.
+pub(crate) fn write_all_records_at(dir_path: &str, records: &[Record]) -> OResult<()> {
+  let data_path = [dir_path, "/o_data"].concat();
+  let records_json = serde_json::to_string_pretty(&get_data_file(records))
+    .map_err(|err| OError::CorruptData(err.to_string()))?;
+
+  write_file_atomically(
+    &data_path,
+    &encode_data(
+      records_json.as_bytes(),
+      &get_config_at(dir_path)?.encryption_key,
+    ),
+  )
+}
 
-  let records_json = serde_json::to_string_pretty(&records).unwrap();
-  file.write_all(records_json.as_bytes()).unwrap();
+pub fn get_journal() -> OResult<Journal> {
+  get_journal_at(&get_project_dir()?)
 }
 
-pub fn get_config() -> Config {
-  let mut file = File::open(".o/o_config.toml");
+pub fn get_journal_at(dir_path: &str) -> OResult<Journal> {
+  let journal_path = [dir_path, "/journal"].concat();
 
-  if file.is_err() {
-    let home_dir_config_path =
-      [home_dir().unwrap().to_str().unwrap(), "/.o/o_config.toml"].concat();
-    file = File::open(&home_dir_config_path);
+  if !get_path_exists(&journal_path) {
+    return Ok(Journal::default());
   }
 
+  let contents = read(&journal_path)?;
+  let plain = decode_project_data(&contents, &get_config_at(dir_path)?, &journal_path)?;
+
+  let mut journal: Journal = serde_json::from_slice(&plain)
+    .map_err(|err| OError::CorruptData(format!("{}: {}", journal_path, err)))?;
+
+  journal.migrate_record_dates();
+
+  Ok(journal)
+}
+
+pub(crate) fn write_journal_at(dir_path: &str, journal: &Journal) -> OResult<()> {
+  let journal_path = [dir_path, "/journal"].concat();
+  let journal_json =
+    serde_json::to_string(journal).map_err(|err| OError::CorruptData(err.to_string()))?;
+
+  write_file_atomically(
+    &journal_path,
+    &encode_data(
+      journal_json.as_bytes(),
+      &get_config_at(dir_path)?.encryption_key,
+    ),
+  )
+}
+
+pub fn get_config() -> OResult<Config> {
+  get_config_at(&get_project_dir()?)
+}
+
+pub fn get_config_at(dir_path: &str) -> OResult<Config> {
+  let config_path = [dir_path, "/o_config.toml"].concat();
   let mut contents = String::new();
-  file
-    .unwrap()
-    .read_to_string(&mut contents)
-    .expect("Unable to read the file");
-
-  toml::from_str(&contents).unwrap()
-}
-
-pub fn handle_csv(matches: &ArgMatches<'_>) {
-  if matches.is_present("import") {
-    let file_path = matches.value_of("import").unwrap();
-
-    let mut rdr = ReaderBuilder::new()
-      .has_headers(false)
-      .from_path(file_path)
-      .unwrap();
-
-    let csv_records: Vec<CSVRecord> = rdr
-      .records()
-      .skip(1)
-      .map(|x| {
-        let result = x.unwrap();
-        CSVRecord {
-          what: result[0].to_string(),
-          location: result[1].to_string(),
-          updated: result[2].to_string(),
-          notes: result[3].to_string(),
-        }
-      })
-      .collect();
-    let csv_records_len = csv_records.len();
-    let mut records: Vec<Record> = vec![];
 
-    let mut what_ids: HashMap<String, usize> = HashMap::new();
-    let mut location_ids: HashMap<String, usize> = HashMap::new();
+  File::open(&config_path)?.read_to_string(&mut contents)?;
+
+  toml::from_str(&contents).map_err(|err| OError::BadConfig(format!("{}: {}", config_path, err)))
+}
+
+fn get_csv_error(file_path: &str, err: csv::Error) -> OError {
+  OError::CorruptData(format!("{}: {}", file_path, err))
+}
 
-    for (idx, csv_record) in csv_records.iter().enumerate() {
-      let what = (*csv_record.what).to_string();
-      let location = (*csv_record).location.to_string();
-      let what_id = idx;
-      let location_id = csv_records_len + idx;
-
-      records.push(Record {
-        what: what.clone(),
-        updated: (*csv_record).updated.to_string(),
-        location: location.clone(),
-        notes: (*csv_record).notes.to_string(),
-        created: (*csv_record).updated.to_string(),
-        what_id,
-        location_id: csv_records_len + idx,
-      });
-
-      if what_ids.get(&what).is_some() {
-        println!("Duplicated 'what' entry: {}", what);
-        std::process::exit(1);
-      } else if location_ids.get(&location).is_none() {
-        location_ids.insert(location, location_id);
+// The columns are found by the names of the header, so they can be in any
+// order and the missing ones are left empty. With the id columns the records
+// keep their ids, otherwise they are given by the names. The attributes are
+// parsed with the types of `schema`. The merge fields are the columns of the
+// file.
+pub fn read_csv_records(
+  file_path: &str,
+  schema: &AttributesSchema,
+  options: &CsvOptions,
+) -> OResult<(Vec<Record>, MergeFields)> {
+  let mut rdr = ReaderBuilder::new()
+    .has_headers(false)
+    .delimiter(options.delimiter)
+    .from_path(file_path)
+    .map_err(|err| get_csv_error(file_path, err))?;
+
+  let mut columns: Vec<String> = if options.has_header {
+    vec![]
+  } else {
+    get_csv_columns(&[], options)?
+  };
+  let mut records: Vec<Record> = vec![];
+  let mut rows_ids: Vec<(Option<usize>, Option<usize>)> = vec![];
+
+  for (row_idx, result) in rdr.records().enumerate() {
+    let result = result.map_err(|err| get_csv_error(file_path, err))?;
+
+    if options.has_header && row_idx == 0 {
+      for column in result.iter() {
+        columns.push(parse_csv_column(column)?);
       }
 
-      what_ids.insert(what, what_id);
+      continue;
+    }
+
+    if result.len() > columns.len() {
+      return Err(OError::CorruptData(format!(
+        "{}: the row {} has {} fields but there are {} columns",
+        file_path,
+        row_idx + 1,
+        result.len(),
+        columns.len()
+      )));
     }
 
-    for (idx, record) in records.clone().iter().enumerate() {
-      if what_ids.get(&record.location).is_some() {
-        records[idx].location_id = what_ids[&record.location];
-      } else if location_ids.get(&record.location).is_some() {
-        records[idx].location_id = location_ids[&record.location];
+    let get_field = |name: &str| {
+      columns
+        .iter()
+        .position(|column| column == name)
+        .and_then(|idx| result.get(idx))
+        .unwrap_or("")
+        .to_string()
+    };
+    let mut attributes = Attributes::new();
+
+    for (idx, column) in columns.iter().enumerate() {
+      let value = result.get(idx).unwrap_or("");
+
+      if get_is_attribute_column(column) && !value.is_empty() {
+        let (key, attribute_type) = split_attribute_column(column);
+        let attribute_type = attribute_type.or_else(|| schema.get(key).cloned());
+
+        attributes.insert(
+          key.to_string(),
+          parse_attribute_value(key, value, attribute_type)?,
+        );
       }
     }
 
-    optimize_records_ids(&mut records);
+    // the missing dates are the time of the import
+    let what = get_field("what");
+    let updated = parse_import_date(&what, "updated", Some(&get_field("updated")))?
+      .unwrap_or_else(get_now_date);
+    let created = parse_import_date(&what, "created", Some(&get_field("created")))?
+      .unwrap_or_else(|| updated.clone());
+    let notes = match get_field("notes") {
+      notes if notes.is_empty() => get_empty_notes_text(),
+      notes => notes,
+    };
+
+    rows_ids.push((
+      parse_csv_number("what_id", &get_field("what_id"))?,
+      parse_csv_number("location_id", &get_field("location_id"))?,
+    ));
+    records.push(Record {
+      what,
+      what_id: 0,
+      location: get_field("location"),
+      location_id: 0,
+      notes,
+      created,
+      updated,
+      tags: get_field("tags")
+        .split(',')
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect(),
+      attributes,
+      quantity: parse_csv_number("quantity", &get_field("quantity"))?,
+      min_quantity: parse_csv_number("min_quantity", &get_field("min_quantity"))?,
+    });
+  }
 
-    write_all_records(&records);
-  } else if matches.is_present("export") {
-    let file_path = matches.value_of("export").unwrap();
-    let records = get_data_records();
-    let mut csv_records: Vec<CSVRecord> = vec![];
-
-    for record in records.clone() {
-      csv_records.push(CSVRecord {
-        what: record.what,
-        location: record.location,
-        updated: record.updated,
-        notes: record.notes,
-      });
+  let mut what_names: HashSet<String> = HashSet::new();
+
+  for record in &records {
+    if !what_names.insert(record.what.clone()) {
+      return Err(OError::DuplicateWhat(record.what.clone()));
     }
+  }
 
-    let mut wtr = csv::Writer::from_path(file_path).unwrap();
-    for record in records {
-      wtr.serialize(&record).unwrap();
+  let has_ids = columns.iter().any(|column| column == "what_id")
+    && columns.iter().any(|column| column == "location_id");
+
+  if has_ids {
+    for (row_idx, (record, ids)) in records.iter_mut().zip(rows_ids).enumerate() {
+      match ids {
+        (Some(what_id), Some(location_id)) => {
+          record.what_id = what_id;
+          record.location_id = location_id;
+        }
+        _ => {
+          return Err(OError::InvalidArgument(format!(
+            "The row {} of {} has no what_id or location_id, all the rows need them",
+            row_idx + 1,
+            file_path
+          )))
+        }
+      }
+    }
+  } else {
+    let records_len = records.len();
+    let mut what_ids: HashMap<String, usize> = HashMap::new();
+    let mut location_ids: HashMap<String, usize> = HashMap::new();
+
+    for (idx, record) in records.iter_mut().enumerate() {
+      record.what_id = idx;
+      record.location_id = records_len + idx;
+
+      location_ids
+        .entry(record.location.clone())
+        .or_insert(record.location_id);
+      what_ids.insert(record.what.clone(), record.what_id);
     }
-    wtr.flush().unwrap();
+
+    for record in records.iter_mut() {
+      if let Some(id) = what_ids
+        .get(&record.location)
+        .or_else(|| location_ids.get(&record.location))
+      {
+        record.location_id = *id;
+      }
+    }
+
+    optimize_records_ids(&mut records);
   }
+
+  let has_column = |name: &str| columns.iter().any(|column| column == name);
+  let fields = MergeFields {
+    location: has_column("location"),
+    notes: has_column("notes"),
+    tags: has_column("tags"),
+    quantity: has_column("quantity"),
+    min_quantity: has_column("min_quantity"),
+    attributes: Some(
+      columns
+        .iter()
+        .filter(|column| get_is_attribute_column(column))
+        .map(|column| split_attribute_column(column).0.to_string())
+        .collect(),
+    ),
+  };
+
+  Ok((records, fields))
 }
 
-pub fn optimize_records_ids(records: &mut Vec<Record>) {
+// Without a header the import only knows the default columns, so the
+// attribute columns must be passed in `columns`
+pub fn write_csv_records(file_path: &str, records: &[Record], options: &CsvOptions) -> OResult<()> {
+  let has_attributes = records.iter().any(|record| !record.attributes.is_empty());
+
+  if !options.has_header && options.columns.is_none() && has_attributes {
+    return Err(OError::InvalidArgument(
+      "The items have attributes, which can't be imported back without a header: pass the columns with --columns, and the same --columns to the import".to_string(),
+    ));
+  }
+
+  let columns = get_csv_columns(records, options)?;
+  let context = get_context(records);
+  let mut wtr = WriterBuilder::new()
+    .delimiter(options.delimiter)
+    .from_path(file_path)
+    .map_err(|err| get_csv_error(file_path, err))?;
+
+  if options.has_header {
+    wtr
+      .write_record(&columns)
+      .map_err(|err| get_csv_error(file_path, err))?;
+  }
+
+  for record in records {
+    let row: Vec<String> = columns
+      .iter()
+      .map(|column| get_csv_value(record, column, &context))
+      .collect();
+
+    wtr
+      .write_record(&row)
+      .map_err(|err| get_csv_error(file_path, err))?;
+  }
+  wtr.flush()?;
+
+  Ok(())
+}
+
+pub fn optimize_records_ids(records: &mut [Record]) {
   fn get_last_correct_id_idx(existing_ids: &[usize], starting_item: usize) -> Option<usize> {
     let offset = starting_item + 1;
     for (idx, existing_id) in existing_ids.iter().skip(offset).enumerate() {
@@ -274,6 +651,10 @@ pub fn optimize_records_ids(records: &mut Vec<Record>) {
   let mut existing_ids = existing_ids_set.iter().cloned().collect::<Vec<usize>>();
   existing_ids.sort();
 
+  if existing_ids.is_empty() {
+    return;
+  }
+
   if existing_ids[0] != 0 {
     let next_wrong_id = existing_ids[0];
     let next_correct_id = 0;
@@ -289,8 +670,7 @@ pub fn optimize_records_ids(records: &mut Vec<Record>) {
 
   let mut last_correct_id_idx = get_last_correct_id_idx(&existing_ids, 0);
 
-  while last_correct_id_idx != None {
-    let last_correct_id_idx_val = last_correct_id_idx.unwrap();
+  while let Some(last_correct_id_idx_val) = last_correct_id_idx {
     let last_correct_id = existing_ids[last_correct_id_idx_val];
     let next_correct_id = last_correct_id + 1;
     let next_wrong_id = existing_ids[last_correct_id_idx_val + 1];
@@ -309,53 +689,258 @@ pub fn optimize_records_ids(records: &mut Vec<Record>) {
   }
 }
 
-pub fn revert_data_to_backup() {
-  let maybe_dir_path = get_config_dir();
+// The copies of `o_data` kept by the versions before the journal, they are no
+// longer read but they can still hold old data
+fn get_legacy_backup_paths(dir_path: &str) -> OResult<Vec<String>> {
+  let backups_dir_path = [dir_path, "/backups"].concat();
 
-  if maybe_dir_path.is_none() {
-    println!("There is no .o directory present locally or in the home directory");
-    std::process::exit(1);
+  if !get_path_exists(&backups_dir_path) {
+    return Ok(vec![]);
   }
 
-  let dir_path = maybe_dir_path.unwrap();
+  let mut paths: Vec<String> = vec![];
 
-  let saved_actions = get_config().saved_actions;
+  for entry in read_dir(&backups_dir_path)? {
+    let path = entry?.path().display().to_string();
 
-  if saved_actions == 0 {
-    println!("Using backup is disabled: `saved_actions` in `.o/o_config.toml`");
-    std::process::exit(1);
+    // leftovers of an interrupted atomic write
+    if !path.ends_with(".tmp") {
+      paths.push(path);
+    }
   }
 
-  let data_path = [&dir_path, "/o_data"].concat();
-  let backups_dir_path = [&dir_path, "/backups"].concat();
+  paths.sort();
 
-  let paths: Vec<String> = read_dir(&backups_dir_path)
-    .unwrap()
-    .map(|x| x.unwrap().path().display().to_string())
+  Ok(paths)
+}
+
+// The legacy backups are rekeyed too, and returned so they can be reported
+pub fn rekey_data(new_key: &str) -> OResult<Vec<String>> {
+  let dir_path = get_project_dir()?;
+  let config_path = [&dir_path, "/o_config.toml"].concat();
+  let config = get_config()?;
+  let legacy_backup_paths = get_legacy_backup_paths(&dir_path)?;
+  let file_paths: Vec<String> = ["/o_data", "/journal"]
+    .iter()
+    .map(|file_name| [&dir_path, *file_name].concat())
+    .filter(|file_path| get_path_exists(file_path))
+    .chain(legacy_backup_paths.iter().cloned())
     .collect();
 
-  if paths.is_empty() {
-    println!("No backups remaining");
-    std::process::exit(1);
+  // every file is decrypted before writing any of them, so a wrong key leaves all untouched
+  let mut plain_contents: Vec<Vec<u8>> = vec![];
+
+  for file_path in &file_paths {
+    plain_contents.push(decode_project_data(&read(file_path)?, &config, file_path)?);
+  }
+
+  let write_files = |encryption_key: &str| -> OResult<()> {
+    for (file_path, plain) in file_paths.iter().zip(&plain_contents) {
+      write_file_atomically(file_path, &encode_data(plain, encryption_key))?;
+    }
+
+    Ok(())
+  };
+  let write_config = |previous_key: Option<&str>| -> OResult<()> {
+    let mut contents = String::new();
+
+    File::open(&config_path)?.read_to_string(&mut contents)?;
+
+    let contents = set_config_value(&contents, "encryption_key", Some(new_key));
+    let contents = set_config_value(&contents, "previous_encryption_key", previous_key);
+
+    write_file_atomically(&config_path, contents.as_bytes())
+  };
+
+  // an interrupted rekey left files under two keys, they are brought back to one
+  if config.previous_encryption_key.is_some() {
+    write_files(&config.encryption_key)?;
   }
 
-  let first_backup_file = [&backups_dir_path, "/o_data_prev_1"].concat();
+  // the config is written first and keeps the old key until every file is
+  // rewritten, so a crash at any point leaves all the files readable
+  write_config(Some(config.encryption_key.as_str()).filter(|key| !key.is_empty()))?;
+  write_files(new_key)?;
+  write_config(None)?;
+
+  Ok(legacy_backup_paths)
+}
+
+// Changes a top-level value of o_config.toml without touching the rest of the
+// file, the comments included. None removes the value.
+fn set_config_value(contents: &str, name: &str, value: Option<&str>) -> String {
+  // a JSON string is also a valid TOML basic string
+  let mut new_line = value.map(|value| {
+    format!(
+      "{} = {}",
+      name,
+      serde_json::to_string(value).unwrap_or_default()
+    )
+  });
+  let mut is_top_level = true;
+  let mut lines: Vec<String> = vec![];
+
+  for line in contents.lines() {
+    let trimmed = line.trim_start();
 
-  remove_file(&data_path).ok();
-  rename(&first_backup_file, &data_path).ok();
+    if trimmed.starts_with('[') {
+      is_top_level = false;
+    }
 
-  for n in 2..=paths.len() {
-    let orig_backup_file_path =
-      [&backups_dir_path, "/o_data_prev_", n.to_string().as_str()].concat();
-    let next_backup_file_path = [
-      &backups_dir_path,
-      "/o_data_prev_",
-      (n - 1).to_string().as_str(),
-    ]
-    .concat();
+    let is_value_line = is_top_level
+      && trimmed
+        .strip_prefix(name)
+        .is_some_and(|rest| rest.trim_start().starts_with('='));
 
-    rename(&orig_backup_file_path, &next_backup_file_path).ok();
+    if !is_value_line {
+      lines.push(line.to_string());
+    } else if let Some(new_line) = new_line.take() {
+      lines.push(new_line);
+    }
   }
 
-  println!("One write action was reverted");
+  // a new value goes first, the tables have to be after the top-level values
+  if let Some(new_line) = new_line {
+    lines.insert(0, new_line);
+  }
+
+  lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+  use std::env::temp_dir;
+  use std::fs::{read_to_string, remove_file};
+
+  use super::*;
+
+  const DATE: &str = "2024-01-02T03:04:05+00:00";
+
+  fn get_record(what_id: usize, what: &str, location_id: usize, location: &str) -> Record {
+    Record {
+      what_id,
+      what: what.to_string(),
+      location_id,
+      location: location.to_string(),
+      created: DATE.to_string(),
+      updated: DATE.to_string(),
+      notes: get_empty_notes_text(),
+      tags: vec![],
+      attributes: Attributes::new(),
+      quantity: None,
+      min_quantity: None,
+    }
+  }
+
+  fn get_records() -> Vec<Record> {
+    let mut records = vec![get_record(1, "box", 0, "shelf")];
+    let mut drill = get_record(2, "drill, \"big\"", 1, "box");
+
+    drill.notes = "say \"hi\", then\nleave; \tnow".to_string();
+    drill.tags = vec!["tools".to_string(), "ü".to_string()];
+    drill.quantity = Some(3);
+    drill.min_quantity = Some(1);
+    drill.attributes.insert(
+      "code".to_string(),
+      AttributeValue::String("19.5".to_string()),
+    );
+    drill
+      .attributes
+      .insert("price".to_string(), AttributeValue::Number(20.5));
+    drill.attributes.insert(
+      "bought".to_string(),
+      AttributeValue::Date("2024-01-31".to_string()),
+    );
+    drill
+      .attributes
+      .insert("ok".to_string(), AttributeValue::Bool(false));
+    records.push(drill);
+
+    records
+  }
+
+  fn get_sorted_json(records: &[Record]) -> serde_json::Value {
+    let mut records = records.to_vec();
+
+    records.sort_by_key(|record| record.what_id);
+
+    serde_json::to_value(&records).unwrap()
+  }
+
+  // Returns the contents of the file and the records read back
+  fn get_round_trip(name: &str, records: &[Record], options: &CsvOptions) -> (String, Vec<Record>) {
+    let file_path = temp_dir()
+      .join(format!("o-test-{}-{}.csv", name, std::process::id()))
+      .to_string_lossy()
+      .to_string();
+
+    write_csv_records(&file_path, records, options).unwrap();
+
+    let contents = read_to_string(&file_path).unwrap();
+    let result = read_csv_records(&file_path, &AttributesSchema::new(), options);
+
+    remove_file(&file_path).unwrap();
+
+    (contents, result.unwrap().0)
+  }
+
+  #[test]
+  fn test_csv_round_trip() {
+    let records = get_records();
+    let (contents, read_records) = get_round_trip("header", &records, &CsvOptions::default());
+
+    assert!(
+      contents.starts_with("what_id,what,location_id,location,created,updated,notes,tags,quantity,min_quantity,bought,code:string,ok,price\n"),
+      "{}",
+      contents
+    );
+    assert!(
+      contents.contains("\"say \"\"hi\"\", then\nleave; \tnow\""),
+      "{}",
+      contents
+    );
+    assert_eq!(get_sorted_json(&read_records), get_sorted_json(&records));
+  }
+
+  #[test]
+  fn test_csv_round_trip_delimiters() {
+    let records = get_records();
+
+    for delimiter in b";\t|" {
+      let options = CsvOptions {
+        delimiter: *delimiter,
+        ..CsvOptions::default()
+      };
+      let (contents, read_records) = get_round_trip("delimiter", &records, &options);
+
+      assert!(contents.starts_with(&format!("what_id{}what", *delimiter as char)));
+      assert_eq!(get_sorted_json(&read_records), get_sorted_json(&records));
+    }
+  }
+
+  #[test]
+  fn test_csv_round_trip_without_header() {
+    let records = get_records();
+    let columns: Vec<String> = CSV_COLUMNS
+      .iter()
+      .chain(["bought", "code:string", "ok", "price"].iter())
+      .map(|column| column.to_string())
+      .collect();
+    let options = CsvOptions {
+      columns: Some(columns),
+      has_header: false,
+      ..CsvOptions::default()
+    };
+    let (contents, read_records) = get_round_trip("columns", &records, &options);
+
+    assert!(contents.starts_with("1,box,0,shelf,"), "{}", contents);
+    assert_eq!(get_sorted_json(&read_records), get_sorted_json(&records));
+
+    let without_columns = CsvOptions {
+      has_header: false,
+      ..CsvOptions::default()
+    };
+
+    assert!(write_csv_records("unused.csv", &records, &without_columns).is_err());
+  }
 }
@@ -0,0 +1,1341 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use chrono::{Duration, Local};
+
+use crate::attributes::{AttributeValue, Attributes};
+use crate::check::{check_records, fix_records, Issue};
+use crate::data::{
+  get_context, get_empty_notes_text, get_is_low_stock, get_now_date, parse_record_timestamp,
+  Context, Record,
+};
+use crate::diff::{diff_data, DataDiff};
+use crate::errors::{OError, OResult};
+use crate::fuzzy::{get_similarity, MIN_SIMILARITY};
+use crate::journal::{diff_records, Journal, JournalEntry};
+use crate::lock::{lock_project_at, ProjectLock};
+use crate::query::Query;
+use crate::storage::{
+  get_config_at, get_data_records_at, get_journal_at, get_project_dir, optimize_records_ids,
+  write_all_records_at, write_journal_at,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeType {
+  All,
+  Root,
+  Leaf,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+  pub skip_what: bool,
+  pub skip_location: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoredRecord {
+  pub record: Record,
+  pub score: f64,
+}
+
+// `quantity` is the total of the items inside that track their quantity
+#[derive(Debug, Clone, Serialize)]
+pub struct LocationCount {
+  pub id: usize,
+  pub name: String,
+  pub count: usize,
+  pub quantity: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TreeSort {
+  #[default]
+  Name,
+  Id,
+  Updated,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ListSort {
+  #[default]
+  Created,
+  Updated,
+  Name,
+  Id,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+  pub root: Option<usize>,
+  pub depth: Option<usize>,
+  pub sort: TreeSort,
+}
+
+// `count` is the number of items inside the node, including the ones hidden
+// by the depth
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeItem {
+  pub id: usize,
+  pub name: String,
+  pub count: usize,
+  pub children: Vec<TreeItem>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PathItem {
+  pub id: usize,
+  pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MovedItem {
+  pub record: Record,
+  pub descendants: usize,
+}
+
+// What happens to the items inside a removed container
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoveMode {
+  Single,
+  Recursive,
+  Reparent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemovedItems {
+  pub removed: Vec<Record>,
+  pub reparented: Vec<Record>,
+}
+
+// What a merge does with the existing items that differ from the imported ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictMode {
+  Skip,
+  Overwrite,
+  Fail,
+}
+
+// The fields of the imported file, a merge only compares and overwrites these.
+// `attributes` are the keys of the file, all of them when it is None.
+#[derive(Debug, Clone)]
+pub struct MergeFields {
+  pub location: bool,
+  pub notes: bool,
+  pub tags: bool,
+  pub quantity: bool,
+  pub min_quantity: bool,
+  pub attributes: Option<Vec<String>>,
+}
+
+impl Default for MergeFields {
+  fn default() -> Self {
+    Self {
+      location: true,
+      notes: true,
+      tags: true,
+      quantity: true,
+      min_quantity: true,
+      attributes: None,
+    }
+  }
+}
+
+impl MergeFields {
+  fn get_attribute_keys(&self, record: &Record, other: &Record) -> Vec<String> {
+    match &self.attributes {
+      Some(keys) => keys.clone(),
+      None => record
+        .attributes
+        .keys()
+        .chain(other.attributes.keys())
+        .cloned()
+        .collect(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MergeSummary {
+  pub inserted: usize,
+  pub updated: usize,
+  pub unchanged: usize,
+  pub skipped: usize,
+}
+
+// The records of a container not updated in a while, oldest first
+#[derive(Debug, Clone, Serialize)]
+pub struct StaleGroup {
+  pub id: usize,
+  pub name: String,
+  pub records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TagCount {
+  pub name: String,
+  pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stats {
+  pub count: usize,
+  pub root_nodes: usize,
+  pub quantity: usize,
+  pub low_stock: usize,
+  pub tags: Vec<TagCount>,
+  // the containers with items that track their quantity
+  pub containers: Vec<LocationCount>,
+}
+
+// In-memory view of the records: mutations are only persisted with `save`,
+// which journals the differences with the records that were loaded
+#[derive(Debug)]
+pub struct Store {
+  records: Vec<Record>,
+  original: Vec<Record>,
+  lock: Option<ProjectLock>,
+  // The `.o` directory, None for the stores built from records, which use the
+  // project of the current directory
+  dir_path: Option<String>,
+}
+
+pub fn get_id_str(context: &Context, id: usize) -> OResult<String> {
+  context
+    .id_to_str_map
+    .get(&id)
+    .cloned()
+    .ok_or(OError::UnknownId(id))
+}
+
+pub fn get_path_items(context: &Context, id: usize) -> Vec<PathItem> {
+  context
+    .get_path_ids(id)
+    .into_iter()
+    .zip(context.get_path_names(id))
+    .map(|(id, name)| PathItem { id, name })
+    .collect()
+}
+
+// The latest updated go first, like in the tree. The sort is stable, so the
+// records with the same value keep the order of creation.
+pub fn sort_records(records: &mut [&Record], sort: ListSort) {
+  match sort {
+    ListSort::Created => records.sort_by_key(|record| parse_record_timestamp(&record.created)),
+    ListSort::Updated => {
+      records.sort_by_key(|record| std::cmp::Reverse(parse_record_timestamp(&record.updated)))
+    }
+    ListSort::Name => records.sort_by_key(|record| record.what.to_lowercase()),
+    ListSort::Id => records.sort_by_key(|record| record.what_id),
+  }
+}
+
+// Tags are lowercase, and they are joined with commas in the CSV files
+fn parse_tag(tag: &str) -> OResult<String> {
+  let tag = tag.trim().to_lowercase();
+
+  if tag.is_empty() || tag.contains(',') {
+    return Err(OError::InvalidArgument(format!(
+      "Invalid tag \"{}\": tags can't be empty or contain commas",
+      tag
+    )));
+  }
+
+  Ok(tag)
+}
+
+// The fields that a merge compares, the ids and dates are not compared
+fn get_is_same_content(record: &Record, other: &Record, fields: &MergeFields) -> bool {
+  (!fields.location || record.location == other.location)
+    && (!fields.notes || record.notes == other.notes)
+    && (!fields.tags || record.tags == other.tags)
+    && (!fields.quantity || record.quantity == other.quantity)
+    && (!fields.min_quantity || record.min_quantity == other.min_quantity)
+    && fields
+      .get_attribute_keys(record, other)
+      .iter()
+      .all(|key| record.attributes.get(key) == other.attributes.get(key))
+}
+
+// Accepts an id or a name, names without an id get `new_id`
+fn resolve_name(context: &Context, name_or_id: &str, new_id: usize) -> OResult<(String, usize)> {
+  if let Ok(id) = name_or_id.parse::<usize>() {
+    return Ok((get_id_str(context, id)?, id));
+  }
+
+  let id = match context.str_to_id_map.get(name_or_id) {
+    Some(id) => *id,
+    None => new_id,
+  };
+
+  Ok((name_or_id.to_string(), id))
+}
+
+impl Store {
+  // Only for reading, the stores that are saved need `open_locked`
+  pub fn open() -> OResult<Self> {
+    Self::open_at(&get_project_dir()?)
+  }
+
+  // Holds the project lock until the store is dropped, so no other process
+  // can write between reading the records and saving them
+  pub fn open_locked(wait: bool) -> OResult<Self> {
+    Self::open_locked_at(&get_project_dir()?, wait)
+  }
+
+  // `dir_path` is the `.o` directory of a project, instead of the one found
+  // from the current directory
+  pub fn open_at(dir_path: &str) -> OResult<Self> {
+    if !Path::new(dir_path).is_dir() {
+      return Err(OError::InvalidArgument(format!(
+        "{} is not a directory, `o init` creates the .o directory of a project",
+        dir_path
+      )));
+    }
+
+    let mut store = Self::from_records(get_data_records_at(dir_path)?);
+
+    store.dir_path = Some(dir_path.to_string());
+
+    Ok(store)
+  }
+
+  pub fn open_locked_at(dir_path: &str, wait: bool) -> OResult<Self> {
+    let lock = lock_project_at(dir_path, wait)?;
+    let mut store = Self::open_at(dir_path)?;
+
+    store.lock = Some(lock);
+
+    Ok(store)
+  }
+
+  pub fn from_records(records: Vec<Record>) -> Self {
+    Self {
+      original: records.clone(),
+      records,
+      lock: None,
+      dir_path: None,
+    }
+  }
+
+  fn get_dir_path(&self) -> OResult<String> {
+    match &self.dir_path {
+      Some(dir_path) => Ok(dir_path.clone()),
+      None => get_project_dir(),
+    }
+  }
+
+  pub fn set_records(&mut self, records: Vec<Record>) {
+    self.records = records;
+  }
+
+  pub fn records(&self) -> &[Record] {
+    &self.records
+  }
+
+  pub fn into_records(mut self) -> Vec<Record> {
+    std::mem::take(&mut self.records)
+  }
+
+  pub fn context(&self) -> Context {
+    get_context(&self.records)
+  }
+
+  // Without the lock another process could have written since the read
+  fn persist(&mut self, journal: &Journal) -> OResult<()> {
+    if self.lock.is_none() {
+      return Err(OError::InvalidArgument(
+        "The store can only be saved when it is opened with Store::open_locked".to_string(),
+      ));
+    }
+
+    let dir_path = self.get_dir_path()?;
+
+    write_all_records_at(&dir_path, &self.records)?;
+    write_journal_at(&dir_path, journal)?;
+
+    self.original = self.records.clone();
+
+    Ok(())
+  }
+
+  // `command` describes the change in the history
+  pub fn save(&mut self, command: &str) -> OResult<()> {
+    let dir_path = self.get_dir_path()?;
+    let saved_actions = get_config_at(&dir_path)?.saved_actions;
+    let changes = diff_records(&self.original, &self.records);
+    let mut journal = get_journal_at(&dir_path)?;
+
+    if !changes.is_empty() && saved_actions > 0 {
+      journal.push(JournalEntry::new(command, changes), saved_actions);
+    }
+
+    self.persist(&journal)
+  }
+
+  // Differences between the loaded records and the current ones
+  pub fn get_unsaved_diff(&self) -> DataDiff {
+    diff_data(&self.original, &self.records)
+  }
+
+  fn get_journal_to_walk(&self) -> OResult<Journal> {
+    let dir_path = self.get_dir_path()?;
+
+    if get_config_at(&dir_path)?.saved_actions == 0 {
+      return Err(OError::BadConfig(
+        "The history is disabled: `saved_actions` in `.o/o_config.toml`".to_string(),
+      ));
+    }
+
+    if !diff_records(&self.original, &self.records).is_empty() {
+      return Err(OError::InvalidArgument(
+        "There are unsaved changes in the store".to_string(),
+      ));
+    }
+
+    get_journal_at(&dir_path)
+  }
+
+  // Returns the entries that were undone, latest first
+  pub fn undo(&mut self, steps: usize) -> OResult<Vec<JournalEntry>> {
+    let mut journal = self.get_journal_to_walk()?;
+    let mut undone: Vec<JournalEntry> = vec![];
+
+    if journal.position == 0 {
+      return Err(OError::EmptyJournal("Nothing to undo".to_string()));
+    }
+
+    while undone.len() < steps && journal.position > 0 {
+      journal.position -= 1;
+
+      let entry = &journal.entries[journal.position];
+
+      self.records = entry.undo(&self.records)?;
+      undone.push(entry.clone());
+    }
+
+    self.persist(&journal)?;
+
+    Ok(undone)
+  }
+
+  // The records as they were `steps` journaled writes ago
+  pub fn get_records_before(&self, steps: usize) -> OResult<Vec<Record>> {
+    let journal = get_journal_at(&self.get_dir_path()?)?;
+
+    if steps > journal.position {
+      return Err(OError::EmptyJournal(format!(
+        "There are only {} operations in the history",
+        journal.position
+      )));
+    }
+
+    let mut records = self.original.clone();
+
+    for entry in journal.entries[journal.position - steps..journal.position]
+      .iter()
+      .rev()
+    {
+      records = entry.undo(&records)?;
+    }
+
+    Ok(records)
+  }
+
+  // Differences from the records `steps` journaled writes ago to the current ones
+  pub fn diff_with_history(&self, steps: usize) -> OResult<DataDiff> {
+    Ok(diff_data(&self.get_records_before(steps)?, &self.records))
+  }
+
+  // Returns the entries that were redone, oldest first
+  pub fn redo(&mut self, steps: usize) -> OResult<Vec<JournalEntry>> {
+    let mut journal = self.get_journal_to_walk()?;
+    let mut redone: Vec<JournalEntry> = vec![];
+
+    if journal.position >= journal.entries.len() {
+      return Err(OError::EmptyJournal("Nothing to redo".to_string()));
+    }
+
+    while redone.len() < steps && journal.position < journal.entries.len() {
+      let entry = &journal.entries[journal.position];
+
+      self.records = entry.redo(&self.records)?;
+      redone.push(entry.clone());
+
+      journal.position += 1;
+    }
+
+    self.persist(&journal)?;
+
+    Ok(redone)
+  }
+
+  fn get_record_idx(&self, context: &Context, id: usize) -> OResult<usize> {
+    context
+      .id_to_record_idx_map
+      .get(&id)
+      .cloned()
+      .ok_or(OError::UnknownId(id))
+  }
+
+  // From the root location down to the item, which can be an id or a name
+  pub fn get_path(&self, name_or_id: &str) -> OResult<Vec<PathItem>> {
+    let context = self.context();
+    let id = match name_or_id.parse::<usize>() {
+      Ok(id) if context.hierarchy.contains_key(&id) => id,
+      Ok(id) => return Err(OError::UnknownId(id)),
+      Err(_) => match context.str_to_id_map.get(name_or_id) {
+        Some(id) => *id,
+        None => {
+          return Err(OError::InvalidArgument(format!(
+            "There is no item or location named {}",
+            name_or_id
+          )))
+        }
+      },
+    };
+
+    Ok(get_path_items(&context, id))
+  }
+
+  pub fn insert(&mut self, what: &str, location: &str, notes: Option<&str>) -> OResult<Record> {
+    let context = self.context();
+    let (what, what_id) = resolve_name(&context, what, context.max_id + 1)?;
+    let (location, location_id) = resolve_name(&context, location, context.max_id + 2)?;
+
+    if self.records.iter().any(|record| record.what == what) {
+      return Err(OError::DuplicateWhat(what));
+    }
+
+    let created = get_now_date();
+    let new_record = Record {
+      what,
+      what_id,
+      location,
+      location_id,
+      notes: notes.map_or_else(get_empty_notes_text, |notes| notes.to_string()),
+      updated: created.clone(),
+      created,
+      tags: vec![],
+      attributes: Attributes::new(),
+      quantity: None,
+      min_quantity: None,
+    };
+
+    self.records.push(new_record.clone());
+
+    Ok(new_record)
+  }
+
+  // Renames an item, or a location in all the records that use it. Renaming
+  // a location to an existing name merges it into that location.
+  pub fn rename(&mut self, id: usize, new_name: &str) -> OResult<usize> {
+    let context = self.context();
+    let mut changed = 0;
+
+    let record_idx = match context.id_to_record_idx_map.get(&id) {
+      Some(record_idx) => *record_idx,
+      None => {
+        if !context.hierarchy.contains_key(&id) {
+          return Err(OError::UnknownId(id));
+        }
+
+        let (new_location, new_location_id) = resolve_name(&context, new_name, context.max_id + 1)?;
+
+        for record in self.records.iter_mut() {
+          if record.location_id == id {
+            record.location_id = new_location_id;
+            record.location = new_location.clone();
+            changed += 1;
+          }
+        }
+
+        return Ok(changed);
+      }
+    };
+
+    if new_name == self.records[record_idx].what {
+      return Ok(changed);
+    }
+
+    let (new_what, _) = resolve_name(&context, new_name, context.max_id + 1)?;
+
+    if context.str_to_id_map.contains_key(&new_what) {
+      return Err(OError::DuplicateWhat(new_what));
+    }
+
+    for record in self.records.iter_mut() {
+      if record.what_id == id {
+        record.what = new_what.clone();
+        record.updated = get_now_date();
+        changed += 1;
+      } else if record.location_id == id {
+        record.location = new_what.clone();
+        changed += 1;
+      }
+    }
+
+    Ok(changed)
+  }
+
+  // The item takes its whole subtree with it, so it can't be moved inside itself
+  pub fn move_item(&mut self, id: usize, new_location: &str) -> OResult<MovedItem> {
+    let context = self.context();
+
+    // a top-level location has no record that could hold its new location
+    if !context.id_to_record_idx_map.contains_key(&id) {
+      if let Some(name) = context.id_to_str_map.get(&id) {
+        return Err(OError::InvalidArgument(format!(
+          "{} [{}] is a top-level location, which can't be moved. Insert it inside the new location with `o in {} $ <location>`",
+          name, id, name
+        )));
+      }
+    }
+
+    let record_idx = self.get_record_idx(&context, id)?;
+    let (location, location_id) = resolve_name(&context, new_location, context.max_id + 1)?;
+    let descendant_ids = context.get_descendant_ids(id);
+
+    if location_id == id || descendant_ids.contains(&location_id) {
+      return Err(OError::InvalidArgument(format!(
+        "Unable to move {} [{}] inside {} [{}]: it would create a cycle",
+        self.records[record_idx].what, id, location, location_id
+      )));
+    }
+
+    let record = &mut self.records[record_idx];
+
+    if record.location != location {
+      record.location = location;
+      record.location_id = location_id;
+      record.updated = get_now_date();
+    }
+
+    Ok(MovedItem {
+      record: record.clone(),
+      descendants: descendant_ids.len(),
+    })
+  }
+
+  pub fn set_notes(&mut self, id: usize, notes: Option<&str>) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+
+    record.notes = notes.map_or_else(get_empty_notes_text, |notes| notes.to_string());
+    record.updated = get_now_date();
+
+    Ok(record.clone())
+  }
+
+  // The quantities can be set to none, so the item stops tracking them
+  pub fn set_quantity(
+    &mut self,
+    id: usize,
+    quantity: Option<usize>,
+    min_quantity: Option<usize>,
+  ) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+
+    if record.quantity != quantity || record.min_quantity != min_quantity {
+      record.quantity = quantity;
+      record.min_quantity = min_quantity;
+      record.updated = get_now_date();
+    }
+
+    Ok(record.clone())
+  }
+
+  // An item without quantity starts from 0
+  pub fn add_quantity(&mut self, id: usize, amount: usize) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+
+    record.quantity = Some(record.quantity.unwrap_or(0) + amount);
+    record.updated = get_now_date();
+
+    Ok(record.clone())
+  }
+
+  pub fn take_quantity(&mut self, id: usize, amount: usize) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+    let quantity = record.quantity.unwrap_or(0);
+
+    if amount > quantity {
+      return Err(OError::InvalidArgument(format!(
+        "Unable to take {} from {} [{}]: there are only {}",
+        amount, record.what, id, quantity
+      )));
+    }
+
+    record.quantity = Some(quantity - amount);
+    record.updated = get_now_date();
+
+    Ok(record.clone())
+  }
+
+  // The imported records are matched with the existing ones by name: the new
+  // ones are appended with new ids and the ones that differ are handled with
+  // `on_conflict`, changing only the `fields` of the file. Nothing is changed
+  // when it fails.
+  pub fn merge(
+    &mut self,
+    imported: &[Record],
+    fields: &MergeFields,
+    on_conflict: ConflictMode,
+  ) -> OResult<MergeSummary> {
+    let context = self.context();
+    let mut summary = MergeSummary::default();
+    let mut new_ids: HashMap<String, usize> = HashMap::new();
+    let mut next_id = context.max_id + 1;
+    let mut get_id = |name: &str| match context.str_to_id_map.get(name) {
+      Some(id) => *id,
+      None => *new_ids.entry(name.to_string()).or_insert_with(|| {
+        next_id += 1;
+        next_id - 1
+      }),
+    };
+    let mut conflicts: Vec<(usize, &Record)> = vec![];
+    let previous_records = self.records.clone();
+
+    // the new items get their ids first, so they keep the order of the file
+    for record in imported {
+      if !context.str_to_id_map.contains_key(&record.what) {
+        get_id(&record.what);
+      }
+    }
+
+    let records_len = self.records.len();
+
+    for record in imported {
+      match self.records[..records_len]
+        .iter()
+        .position(|existing| existing.what == record.what)
+      {
+        Some(record_idx) if get_is_same_content(&self.records[record_idx], record, fields) => {
+          summary.unchanged += 1;
+        }
+        Some(record_idx) => conflicts.push((record_idx, record)),
+        None => {
+          self.records.push(Record {
+            what_id: get_id(&record.what),
+            location_id: get_id(&record.location),
+            ..record.clone()
+          });
+          summary.inserted += 1;
+        }
+      }
+    }
+
+    match on_conflict {
+      ConflictMode::Fail if !conflicts.is_empty() => {
+        self.records = previous_records;
+
+        return Err(OError::InvalidArgument(format!(
+          "{} item(s) differ from the imported ones: {}. Use --on-conflict skip or overwrite",
+          conflicts.len(),
+          conflicts
+            .iter()
+            .map(|(_, record)| record.what.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+        )));
+      }
+      ConflictMode::Skip | ConflictMode::Fail => summary.skipped = conflicts.len(),
+      ConflictMode::Overwrite => {
+        for (record_idx, record) in conflicts {
+          let id = self.records[record_idx].what_id;
+          let location_id = if fields.location {
+            get_id(&record.location)
+          } else {
+            self.records[record_idx].location_id
+          };
+          let current_context = self.context();
+
+          if fields.location
+            && (location_id == id
+              || current_context
+                .get_descendant_ids(id)
+                .contains(&location_id))
+          {
+            self.records = previous_records;
+
+            return Err(OError::InvalidArgument(format!(
+              "Unable to move {} [{}] inside {}: it would create a cycle",
+              record.what, id, record.location
+            )));
+          }
+
+          let attribute_keys = fields.get_attribute_keys(&self.records[record_idx], record);
+          let existing = &mut self.records[record_idx];
+
+          if fields.location {
+            existing.location = record.location.clone();
+            existing.location_id = location_id;
+          }
+
+          if fields.notes {
+            existing.notes = record.notes.clone();
+          }
+
+          if fields.tags {
+            existing.tags = record.tags.clone();
+          }
+
+          if fields.quantity {
+            existing.quantity = record.quantity;
+          }
+
+          if fields.min_quantity {
+            existing.min_quantity = record.min_quantity;
+          }
+
+          for key in attribute_keys {
+            match record.attributes.get(&key) {
+              Some(value) => existing.attributes.insert(key, value.clone()),
+              None => existing.attributes.remove(&key),
+            };
+          }
+
+          existing.updated = get_now_date();
+          summary.updated += 1;
+        }
+      }
+    }
+
+    Ok(summary)
+  }
+
+  // Marks the items as verified now, without changing anything else
+  pub fn touch(&mut self, ids: &[usize]) -> OResult<Vec<Record>> {
+    let context = self.context();
+    let mut touched: Vec<Record> = vec![];
+
+    for id in ids {
+      let record_idx = self.get_record_idx(&context, *id)?;
+      let record = &mut self.records[record_idx];
+
+      record.updated = get_now_date();
+      touched.push(record.clone());
+    }
+
+    Ok(touched)
+  }
+
+  // The records located directly inside `id`
+  pub fn get_children(&self, id: usize) -> Vec<&Record> {
+    self
+      .records
+      .iter()
+      .filter(|record| record.location_id == id && record.what_id != id)
+      .collect()
+  }
+
+  // Attributes without a value are removed
+  pub fn set_attributes(
+    &mut self,
+    id: usize,
+    attributes: &[(String, Option<AttributeValue>)],
+  ) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+    let previous_attributes = record.attributes.clone();
+
+    for (key, value) in attributes {
+      match value {
+        Some(value) => record.attributes.insert(key.clone(), value.clone()),
+        None => record.attributes.remove(key),
+      };
+    }
+
+    if record.attributes != previous_attributes {
+      record.updated = get_now_date();
+    }
+
+    Ok(record.clone())
+  }
+
+  // Tags are kept in the order they were added, without repetitions
+  pub fn add_tags(&mut self, id: usize, tags: &[&str]) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+
+    for tag in tags {
+      let tag = parse_tag(tag)?;
+
+      if !record.tags.contains(&tag) {
+        record.tags.push(tag);
+        record.updated = get_now_date();
+      }
+    }
+
+    Ok(record.clone())
+  }
+
+  pub fn remove_tags(&mut self, id: usize, tags: &[&str]) -> OResult<Record> {
+    let context = self.context();
+    let record_idx = self.get_record_idx(&context, id)?;
+    let record = &mut self.records[record_idx];
+    let tags: Vec<String> = tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+    let tags_len = record.tags.len();
+
+    record
+      .tags
+      .retain(|tag| !tags.contains(&tag.to_lowercase()));
+
+    if record.tags.len() != tags_len {
+      record.updated = get_now_date();
+    }
+
+    Ok(record.clone())
+  }
+
+  // `id` can also be a location without its own record, which is removed
+  // when its items are. With `RemoveMode::Single` a container is refused, so
+  // no record is left pointing at a missing location.
+  pub fn remove(&mut self, id: usize, mode: RemoveMode) -> OResult<RemovedItems> {
+    let context = self.context();
+
+    if !context.hierarchy.contains_key(&id) {
+      return Err(OError::UnknownId(id));
+    }
+
+    let name = get_id_str(&context, id)?;
+    let children_count = self.get_children(id).len();
+    let mut reparented: Vec<Record> = vec![];
+
+    let removed_ids: Vec<usize> = match mode {
+      RemoveMode::Single if children_count > 0 => {
+        return Err(OError::InvalidArgument(format!(
+          "Unable to remove {} [{}]: it contains {} item(s), use --recursive or --reparent",
+          name, id, children_count
+        )));
+      }
+      RemoveMode::Single => vec![id],
+      RemoveMode::Recursive => {
+        let mut removed_ids = context.get_descendant_ids(id);
+
+        removed_ids.push(id);
+
+        removed_ids
+      }
+      RemoveMode::Reparent => {
+        let record_idx = context.id_to_record_idx_map.get(&id).ok_or_else(|| {
+          OError::InvalidArgument(format!(
+            "Unable to reparent the items in {} [{}]: it has no parent",
+            name, id
+          ))
+        })?;
+        let parent = self.records[*record_idx].clone();
+
+        for record in self.records.iter_mut() {
+          if record.location_id == id && record.what_id != id {
+            record.location = parent.location.clone();
+            record.location_id = parent.location_id;
+            record.updated = get_now_date();
+            reparented.push(record.clone());
+          }
+        }
+
+        vec![id]
+      }
+    };
+
+    let (removed, records): (Vec<Record>, Vec<Record>) = std::mem::take(&mut self.records)
+      .into_iter()
+      .partition(|record| removed_ids.contains(&record.what_id));
+
+    self.records = records;
+
+    Ok(RemovedItems {
+      removed,
+      reparented,
+    })
+  }
+
+  pub fn check(&self) -> Vec<Issue> {
+    check_records(&self.records)
+  }
+
+  // Returns the issues that remain after the safe repairs
+  pub fn fix(&mut self) -> Vec<Issue> {
+    self.records = fix_records(&self.records);
+
+    self.check()
+  }
+
+  pub fn optimize_ids(&mut self) {
+    optimize_records_ids(&mut self.records);
+  }
+
+  // A record matches when any of the contents is its id (or its location id)
+  // or is contained in its what (or location) or in a tag, ignoring the case
+  pub fn search(&self, contents: &[&str], options: &SearchOptions) -> Vec<&Record> {
+    let contents_l: Vec<String> = contents
+      .iter()
+      .map(|content| content.to_ascii_lowercase())
+      .collect();
+
+    self
+      .records
+      .iter()
+      .filter(|record| {
+        let what_l = record.what.to_ascii_lowercase();
+        let location_l = record.location.to_ascii_lowercase();
+        let tags_l: Vec<String> = record
+          .tags
+          .iter()
+          .map(|tag| tag.to_ascii_lowercase())
+          .collect();
+
+        contents_l.iter().any(|content_l| {
+          if let Ok(id) = content_l.parse::<usize>() {
+            if !options.skip_what && record.what_id == id
+              || !options.skip_location && record.location_id == id
+            {
+              return true;
+            }
+          }
+
+          !options.skip_what && what_l.contains(content_l)
+            || !options.skip_location && location_l.contains(content_l)
+            || tags_l.iter().any(|tag_l| tag_l.contains(content_l))
+        })
+      })
+      .collect()
+  }
+
+  // Each content is scored with the closest of what, location, tags and notes
+  // (all weigh less than what) and the record gets the average, best matches
+  // first
+  pub fn fuzzy_search(
+    &self,
+    contents: &[&str],
+    options: &SearchOptions,
+    limit: usize,
+  ) -> Vec<ScoredRecord> {
+    let get_field_score = |content: &str, text: &str, weight: f64| {
+      let similarity = get_similarity(content, text);
+
+      if similarity >= MIN_SIMILARITY {
+        similarity * weight
+      } else {
+        0.0
+      }
+    };
+    let mut scored_records: Vec<ScoredRecord> = self
+      .records
+      .iter()
+      .map(|record| {
+        let total: f64 = contents
+          .iter()
+          .map(|content| {
+            let mut scores = vec![get_field_score(content, &record.notes, 0.6)];
+
+            for tag in &record.tags {
+              scores.push(get_field_score(content, tag, 0.8));
+            }
+
+            if !options.skip_what {
+              scores.push(get_field_score(content, &record.what, 1.0));
+
+              if content.parse::<usize>() == Ok(record.what_id) {
+                scores.push(1.0);
+              }
+            }
+
+            if !options.skip_location {
+              scores.push(get_field_score(content, &record.location, 0.8));
+            }
+
+            scores.into_iter().fold(0.0, f64::max)
+          })
+          .sum();
+
+        ScoredRecord {
+          record: record.clone(),
+          score: total / contents.len().max(1) as f64,
+        }
+      })
+      .filter(|scored_record| scored_record.score > 0.0)
+      .collect();
+
+    scored_records.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
+    scored_records.truncate(limit);
+
+    scored_records
+  }
+
+  pub fn list(&self, node_type: NodeType) -> Vec<&Record> {
+    let context = self.context();
+
+    self
+      .records
+      .iter()
+      .filter(|record| match node_type {
+        NodeType::All => true,
+        NodeType::Root => context.hierarchy[&record.location_id].parent.is_none(),
+        NodeType::Leaf => context.hierarchy[&record.what_id].children.is_empty(),
+      })
+      .collect()
+  }
+
+  // The items and units at any depth under the location, like in `o tree`
+  fn get_location_count(&self, context: &Context, id: usize) -> LocationCount {
+    let descendant_ids = context.get_descendant_ids(id);
+    let quantities: Vec<usize> = descendant_ids
+      .iter()
+      .filter_map(|descendant_id| context.id_to_record_idx_map.get(descendant_id))
+      .filter_map(|record_idx| self.records[*record_idx].quantity)
+      .collect();
+
+    LocationCount {
+      id,
+      name: context.id_to_str_map[&id].clone(),
+      count: descendant_ids.len(),
+      quantity: if quantities.is_empty() {
+        None
+      } else {
+        Some(quantities.iter().sum())
+      },
+    }
+  }
+
+  pub fn root_locations(&self) -> Vec<LocationCount> {
+    let context = self.context();
+    let mut locations: Vec<LocationCount> = vec![];
+
+    for record in self.list(NodeType::Root) {
+      if locations
+        .iter()
+        .all(|location| location.id != record.location_id)
+      {
+        locations.push(self.get_location_count(&context, record.location_id));
+      }
+    }
+
+    locations
+  }
+
+  pub fn tree(&self, options: &TreeOptions) -> OResult<Vec<TreeItem>> {
+    self.build_tree(options, None)
+  }
+
+  // Keeps the records that match and their ancestors
+  pub fn filtered_tree(&self, query: &Query, options: &TreeOptions) -> OResult<Vec<TreeItem>> {
+    let context = self.context();
+    let mut visible_ids: HashSet<usize> = HashSet::new();
+
+    for record in self.records.iter().filter(|record| query.matches(record)) {
+      visible_ids.extend(context.get_path_ids(record.what_id));
+    }
+
+    self.build_tree(options, Some(&visible_ids))
+  }
+
+  // Names are compared ignoring the case, the latest updated go first and
+  // the ties are sorted by name, so the order is always the same
+  fn sort_ids(&self, ids: &mut [usize], context: &Context, sort: TreeSort) {
+    let get_name = |id: &usize| {
+      context
+        .id_to_str_map
+        .get(id)
+        .map(|name| name.to_lowercase())
+        .unwrap_or_default()
+    };
+    let get_updated = |id: &usize| {
+      context
+        .id_to_record_idx_map
+        .get(id)
+        .and_then(|record_idx| parse_record_timestamp(&self.records[*record_idx].updated))
+    };
+
+    match sort {
+      TreeSort::Name => ids.sort_by_key(|id| (get_name(id), *id)),
+      TreeSort::Id => ids.sort(),
+      TreeSort::Updated => ids.sort_by(|a, b| {
+        get_updated(b)
+          .cmp(&get_updated(a))
+          .then_with(|| get_name(a).cmp(&get_name(b)))
+          .then_with(|| a.cmp(b))
+      }),
+    }
+  }
+
+  fn build_tree(
+    &self,
+    options: &TreeOptions,
+    visible_ids: Option<&HashSet<usize>>,
+  ) -> OResult<Vec<TreeItem>> {
+    // `path` stops the recursion when the data has cycles
+    fn build_item(
+      store: &Store,
+      id: usize,
+      context: &Context,
+      options: &TreeOptions,
+      visible_ids: Option<&HashSet<usize>>,
+      path: &mut Vec<usize>,
+    ) -> TreeItem {
+      let is_expanded = options.depth.is_none_or(|depth| path.len() < depth);
+      let mut child_ids: Vec<usize> = match context.hierarchy.get(&id) {
+        Some(tree_node) if is_expanded => tree_node
+          .children
+          .iter()
+          .filter(|child_id| **child_id != id && !path.contains(child_id))
+          .filter(|child_id| visible_ids.is_none_or(|ids| ids.contains(child_id)))
+          .cloned()
+          .collect(),
+        _ => vec![],
+      };
+
+      store.sort_ids(&mut child_ids, context, options.sort);
+      path.push(id);
+
+      let children = child_ids
+        .into_iter()
+        .map(|child_id| build_item(store, child_id, context, options, visible_ids, path))
+        .collect();
+
+      path.pop();
+
+      TreeItem {
+        id,
+        name: context.id_to_str_map[&id].clone(),
+        count: context.get_descendant_ids(id).len(),
+        children,
+      }
+    }
+
+    let context = self.context();
+    let mut root_ids: Vec<usize> = match options.root {
+      Some(id) if !context.hierarchy.contains_key(&id) => return Err(OError::UnknownId(id)),
+      Some(id) => vec![id],
+      None => context
+        .hierarchy
+        .iter()
+        .filter(|(_, tree_node)| tree_node.parent.is_none())
+        .map(|(id, _)| *id)
+        .collect(),
+    };
+
+    root_ids.retain(|id| visible_ids.is_none_or(|ids| ids.contains(id)));
+    self.sort_ids(&mut root_ids, &context, options.sort);
+
+    Ok(
+      root_ids
+        .into_iter()
+        .map(|id| build_item(self, id, &context, options, visible_ids, &mut vec![]))
+        .collect(),
+    )
+  }
+
+  // The items updated before `older_than` ago, inside `under` when present.
+  // The containers with the oldest items go first, and the records with dates
+  // that can't be parsed are considered stale.
+  pub fn stale(&self, older_than: Duration, under: Option<usize>) -> OResult<Vec<StaleGroup>> {
+    let context = self.context();
+    let limit = Local::now() - older_than;
+    let under_ids: Option<Vec<usize>> = match under {
+      Some(id) if !context.hierarchy.contains_key(&id) => return Err(OError::UnknownId(id)),
+      Some(id) => Some(context.get_descendant_ids(id)),
+      None => None,
+    };
+    let mut records: Vec<&Record> = self
+      .records
+      .iter()
+      .filter(|record| {
+        under_ids
+          .as_ref()
+          .is_none_or(|ids| ids.contains(&record.what_id))
+      })
+      .filter(|record| {
+        parse_record_timestamp(&record.updated).is_none_or(|updated| updated < limit)
+      })
+      .collect();
+
+    records.sort_by_key(|record| parse_record_timestamp(&record.updated));
+
+    let mut groups: Vec<StaleGroup> = vec![];
+
+    for record in records {
+      match groups
+        .iter_mut()
+        .find(|group| group.id == record.location_id)
+      {
+        Some(group) => group.records.push(record.clone()),
+        None => groups.push(StaleGroup {
+          id: record.location_id,
+          name: record.location.clone(),
+          records: vec![record.clone()],
+        }),
+      }
+    }
+
+    Ok(groups)
+  }
+
+  pub fn stats(&self) -> Stats {
+    let context = self.context();
+
+    let mut tags: Vec<TagCount> = vec![];
+
+    for tag in self.records.iter().flat_map(|record| &record.tags) {
+      match tags.iter_mut().find(|tag_count| tag_count.name == *tag) {
+        Some(tag_count) => tag_count.count += 1,
+        None => tags.push(TagCount {
+          name: tag.clone(),
+          count: 1,
+        }),
+      }
+    }
+
+    tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+    // every location above an item with a quantity
+    let mut container_ids: Vec<usize> = self
+      .records
+      .iter()
+      .filter(|record| record.quantity.is_some() && record.location_id != record.what_id)
+      .flat_map(|record| context.get_path_ids(record.location_id))
+      .collect::<HashSet<usize>>()
+      .into_iter()
+      .collect();
+
+    container_ids.sort();
+
+    let mut containers: Vec<LocationCount> = container_ids
+      .into_iter()
+      .map(|id| self.get_location_count(&context, id))
+      .collect();
+
+    containers.sort_by(|a, b| b.quantity.cmp(&a.quantity).then_with(|| a.id.cmp(&b.id)));
+
+    Stats {
+      count: self.records.len(),
+      root_nodes: context
+        .hierarchy
+        .values()
+        .filter(|tree_node| tree_node.parent.is_none())
+        .count(),
+      quantity: self
+        .records
+        .iter()
+        .filter_map(|record| record.quantity)
+        .sum(),
+      low_stock: self
+        .records
+        .iter()
+        .filter(|record| get_is_low_stock(record))
+        .count(),
+      tags,
+      containers,
+    }
+  }
+}